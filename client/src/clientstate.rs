@@ -2,9 +2,17 @@ use bevy::prelude::*;
 use egui::Color32;
 use egui::Stroke;
 use rust_scribble_common::gamestate_common::*;
-use rust_scribble_common::messages_common::ChatMessage;
+use rust_scribble_common::messages_common::{ChatMessage, GameEvent};
+use rust_scribble_common::settings_common::LobbySettings;
 use serde::{Deserialize, Serialize};
 
+/// a line of the chat, either written by a player or an event of the lobby
+#[derive(Serialize, Deserialize)]
+pub enum ChatEntry {
+    Chat(ChatMessage),
+    Event(GameEvent),
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ClientState {
     /// the clients stroke settings for drawing
@@ -15,12 +23,18 @@ pub struct ClientState {
     pub lines: Vec<Line>,
     /// clients text in the input field of the chat section
     pub chat_message_input: String,
-    /// all messages in chat
-    pub chat_messages: Vec<ChatMessage>,
+    /// all messages and events in chat
+    pub chat_messages: Vec<ChatEntry>,
     /// the game state
     pub game_state: GameState,
     /// Players in the game
     pub players: Vec<Player>,
+    /// the settings of the lobby, none until the server sent them
+    pub settings: Option<LobbySettings>,
+    /// the settings the host is editing but has not applied yet
+    pub settings_draft: Option<LobbySettings>,
+    /// the custom words the host is typing, separated by commas or new lines
+    pub custom_words_input: String,
 }
 
 impl Default for ClientState {
@@ -33,6 +47,9 @@ impl Default for ClientState {
             chat_messages: Vec::new(),
             game_state: GameState::default(),
             players: Vec::new(),
+            settings: None,
+            settings_draft: None,
+            custom_words_input: String::new(),
         }
     }
 }
@@ -0,0 +1,84 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+/// File in which the fingerprints of known servers are stored.
+const KNOWN_SERVERS_FILE: &str = "known_servers.json";
+
+/// Trust-on-first-use store of server identity fingerprints.
+///
+/// The first time the client connects to a server its fingerprint is remembered,
+/// every later connection to the same address has to present the same identity.
+pub struct KnownServers {
+    /// fingerprints by server address
+    servers: BTreeMap<String, String>,
+}
+
+impl KnownServers {
+    /// Loads the known servers from disk, a missing file counts as empty.
+    ///
+    /// # Returns
+    /// * `Ok(known_servers)` - The pinned fingerprints.
+    /// * `Err(e)` - The file exists but cannot be read or parsed, trusting any server would switch off the pinning.
+    pub fn load() -> Result<Self, Error> {
+        let content = match fs::read(KNOWN_SERVERS_FILE) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Ok(KnownServers {
+                    servers: BTreeMap::new(),
+                })
+            }
+            Err(e) => return Err(e),
+        };
+        let servers = serde_json::from_slice(&content).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("{} is corrupt: {}", KNOWN_SERVERS_FILE, e),
+            )
+        })?;
+        Ok(KnownServers { servers })
+    }
+
+    /// Checks the fingerprint a server presented against the pinned one.
+    ///
+    /// # Arguments
+    /// * `server` - The address of the server.
+    /// * `fingerprint` - The fingerprint of the identity key the server presented.
+    ///
+    /// # Returns
+    /// * `Ok(())` - The server is unknown or presented the pinned identity.
+    /// * `Err(e)` - The identity of the server has changed.
+    pub fn check(&self, server: &str, fingerprint: &str) -> Result<(), Error> {
+        match self.servers.get(server) {
+            Some(pinned) if pinned != fingerprint => Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!(
+                    "Identity of {} has changed (pinned {}, got {}), refusing to connect",
+                    server, pinned, fingerprint
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Pins the fingerprint of a server whose handshake was verified and stores it on disk.
+    ///
+    /// # Arguments
+    /// * `server` - The address of the server.
+    /// * `fingerprint` - The fingerprint of the verified identity key.
+    pub fn pin(&mut self, server: &str, fingerprint: &str) -> Result<(), Error> {
+        if self.servers.contains_key(server) {
+            return Ok(());
+        }
+        println!(
+            "Trusting new server {} with fingerprint {}",
+            server, fingerprint
+        );
+        self.servers
+            .insert(server.to_string(), fingerprint.to_string());
+        fs::write(
+            KNOWN_SERVERS_FILE,
+            serde_json::to_vec_pretty(&self.servers)?,
+        )
+    }
+}
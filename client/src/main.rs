@@ -1,5 +1,6 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 mod clientstate;
+mod known_servers;
 mod network;
 mod network_plugin;
 mod ui;
@@ -1,12 +1,20 @@
-use chacha20poly1305::Key;
+use rust_scribble_common::handshake_common::{
+    ClientHello, ClientKeyExchange, EncryptionMode, HandshakeFinished, LobbyInfo, LobbyRequest,
+    LobbySummary, ServerHello,
+};
+use rust_scribble_common::messages_common::Message;
 use rust_scribble_common::network_common::*;
 use std::error;
-use std::io::{Error, ErrorKind, Read, Write};
-use std::net::TcpStream;
+use std::io::{Error, ErrorKind};
+use std::net::{SocketAddrV4, TcpStream};
 use std::str;
 use std::time::Duration;
 use x25519_dalek::PublicKey;
 
+use crate::known_servers::KnownServers;
+
+const HANDSHAKE_TIMEOUT: u64 = 5; // seconds
+
 /// Try and read messages from the server
 ///
 /// # Arguments
@@ -14,20 +22,38 @@ use x25519_dalek::PublicKey;
 /// * `number_of_messages` - The number of messages to try and read.
 ///
 /// # Returns
-/// * `Ok(messages) - A vector of JSON value messages
-/// * `Err(error) - An error if something went wrong
+/// * `Ok(messages) - A vector of messages, if the connection broke after them the next call reports it
+/// * `Err(error) - The connection broke and has to be closed
 ///
 pub fn read_messages(
     net_info: &mut NetworkInfo,
     n_msg_to_read: u8,
-) -> Result<Vec<serde_json::Value>, Box<dyn error::Error>> {
+) -> Result<Vec<Message>, Box<dyn error::Error>> {
     let mut messages = Vec::new();
     for _ in 0..=n_msg_to_read {
         match read_tcp_message(net_info) {
             Ok(msg) => {
                 messages.push(msg);
             }
-            Err(_) => {
+            Err(e) if e.is::<serde_json::Error>() => {
+                println!("Ignoring malformed message from server: {}", e);
+            }
+            Err(e) if e.is::<ProtocolError>() => {
+                if e.downcast_ref::<ProtocolError>().unwrap().is_fatal() {
+                    println!("Closing connection to server: {}", e);
+                    return Err(e);
+                }
+                println!("Ignoring message from server: {}", e);
+            }
+            Err(e) => {
+                let waiting = matches!(
+                    e.downcast_ref::<Error>().map(Error::kind),
+                    Some(ErrorKind::WouldBlock | ErrorKind::TimedOut)
+                );
+                // the messages sent before the server closed the connection are handled first
+                if !waiting && messages.is_empty() {
+                    return Err(e);
+                }
                 break;
             }
         }
@@ -38,49 +64,181 @@ pub fn read_messages(
 
 /// Connects to the server and returns a NetworkInfo struct
 ///
-/// Attempts to connect to the server and generate a
-/// shared key for encrypted communication with the server.
+/// Attempts to connect to the server, agree on the protocol version and encryption mode
+/// and generate a shared key for the communication with the server.
+/// The handshake has to be signed by the identity key pinned for the server,
+/// or by any identity key if the client has never connected to the server before.
 ///
 /// # Arguments
 /// * `ip_addr` - The address of the server.
 /// * `port` - The port of the server.
 /// * `username` - The username of the client.
+/// * `lobby` - The lobby the client wants to join.
 ///
 /// # Returns
-/// * `Ok(net_info)` - A NetworkInfo struct containing the tcp_stream and the key.
+/// * `Ok((net_info, lobby_info))` - A NetworkInfo struct containing the tcp_stream and the key
+///   and the lobby the server placed the client in.
 /// * `Err(e)` - The error that occurred.
 ///
-pub fn connect_to_server(ip_addr: &str, port: u16, username: &str) -> Result<NetworkInfo, Error> {
+pub fn connect_to_server(
+    ip_addr: &str,
+    port: u16,
+    username: &str,
+    lobby: LobbyRequest,
+) -> Result<(NetworkInfo, LobbyInfo), Error> {
     let (public_key, secret_key) = generate_keypair();
+    let (mut tcp_stream, socket) = open_connection(ip_addr, port)?;
+    let mut decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
 
-    let ip_addr = ip_addr.parse::<std::net::Ipv4Addr>().unwrap();
-    let socket = std::net::SocketAddrV4::new(ip_addr, port);
-    if let Ok(mut tcp_stream) = TcpStream::connect(socket) {
-        println!("Connected to the server!");
+    let offered_modes = EncryptionMode::default_modes();
+    let hello = ClientHello::new(
+        offered_modes.clone(),
+        username.to_string(),
+        env!("CARGO_PKG_VERSION").to_string(),
+        lobby,
+    );
+    // both hellos are signed as sent, so nobody on the way can change them
+    let hello_body = serde_json::to_vec(&hello)?;
+    send_tcp_message(&mut tcp_stream, encode_handshake_frame(&hello_body)?)?;
+    let server_hello_body = read_handshake_body(&mut tcp_stream, &mut decoder)?;
+    let (mode, id, server_key, identity_key, lobby_info) =
+        match serde_json::from_slice(&server_hello_body)? {
+            ServerHello::Accept {
+                protocol_version,
+                encryption_mode,
+                id,
+                public_key,
+                identity_key,
+                lobby,
+            } if protocol_version == PROTOCOL_VERSION
+                && offered_modes.contains(&encryption_mode) =>
+            {
+                (
+                    encryption_mode,
+                    id,
+                    PublicKey::from(public_key),
+                    identity_key,
+                    lobby,
+                )
+            }
+            ServerHello::Accept { .. } => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Server chose a protocol version or encryption mode we did not offer",
+                ))
+            }
+            ServerHello::LobbyList { .. } => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Server sent a lobby list instead of joining a lobby",
+                ))
+            }
+            ServerHello::Reject { reason } => {
+                return Err(Error::new(
+                    ErrorKind::ConnectionRefused,
+                    format!("Server refused the connection: {}", reason),
+                ))
+            }
+        };
 
-        let mut buffer = [0; 32];
-        let _ = tcp_stream.read(&mut buffer)?;
-        let server_key: PublicKey = PublicKey::from(buffer);
-        let mut id_buffer = [0; 8];
-        let _ = tcp_stream.read(&mut id_buffer)?;
-        let id: i64 = i64::from_be_bytes(id_buffer);
+    let server_name = socket.to_string();
+    let server_fingerprint = fingerprint(&identity_key);
+    let mut known_servers = KnownServers::load()?;
+    known_servers.check(&server_name, &server_fingerprint)?;
 
-        println!("Received id {}!", id);
-        tcp_stream.write_all(public_key.as_bytes())?;
-        tcp_stream.write_all(username.as_bytes())?;
+    println!("Received id {}!", id);
+    send_handshake_message(
+        &mut tcp_stream,
+        &ClientKeyExchange::new(public_key.to_bytes()),
+    )?;
 
-        let _ = tcp_stream.set_read_timeout(Some(Duration::from_millis(30)));
+    let finished: HandshakeFinished = read_handshake_message(&mut tcp_stream, &mut decoder)?;
+    let signature: [u8; 64] = finished
+        .signature
+        .try_into()
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "Invalid server signature"))?;
+    let transcript = handshake_transcript(
+        &hello_body,
+        &server_hello_body,
+        &server_key,
+        id,
+        &identity_key,
+        &public_key,
+        mode,
+    );
+    verify_handshake_signature(&identity_key, &transcript, &signature)?;
+    known_servers.pin(&server_name, &server_fingerprint)?;
 
-        let shared_secret = secret_key.diffie_hellman(&server_key);
-        let key: chacha20poly1305::Key = *Key::from_slice(shared_secret.as_bytes());
+    let _ = tcp_stream.set_read_timeout(Some(Duration::from_millis(30)));
 
-        Ok(NetworkInfo {
-            id,
-            tcp_stream,
-            key,
-            secret_key: None,
-        })
-    } else {
-        Err(Error::new(ErrorKind::Other, "Failed to connect to server"))
+    let shared_secret = secret_key.diffie_hellman(&server_key);
+    let session = Session::new(shared_secret.as_bytes(), &transcript, Side::Client, mode);
+    println!("Connected in {} mode to lobby {}", mode, lobby_info.name);
+
+    let network_info = NetworkInfo {
+        id,
+        tcp_stream,
+        session,
+        decoder,
+    };
+    Ok((network_info, lobby_info))
+}
+
+/// Asks the server for its public lobbies
+///
+/// The server answers the hello with the list and closes the connection,
+/// so no keys are exchanged.
+///
+/// # Arguments
+/// * `ip_addr` - The address of the server.
+/// * `port` - The port of the server.
+/// * `username` - The username of the client.
+///
+/// # Returns
+/// * `Ok(lobbies)` - The public lobbies of the server.
+/// * `Err(e)` - The error that occurred.
+///
+pub fn list_lobbies(ip_addr: &str, port: u16, username: &str) -> Result<Vec<LobbySummary>, Error> {
+    let (mut tcp_stream, _) = open_connection(ip_addr, port)?;
+    let mut decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+    let hello = ClientHello::new(
+        EncryptionMode::default_modes(),
+        username.to_string(),
+        env!("CARGO_PKG_VERSION").to_string(),
+        LobbyRequest::Browse,
+    );
+    send_handshake_message(&mut tcp_stream, &hello)?;
+    match read_handshake_message(&mut tcp_stream, &mut decoder)? {
+        ServerHello::LobbyList { lobbies } => Ok(lobbies),
+        ServerHello::Accept { .. } => Err(Error::new(
+            ErrorKind::InvalidData,
+            "Server accepted the connection instead of sending the lobby list",
+        )),
+        ServerHello::Reject { reason } => Err(Error::new(
+            ErrorKind::ConnectionRefused,
+            format!("Server refused the connection: {}", reason),
+        )),
     }
 }
+
+/// Opens a connection to the server for the handshake
+///
+/// # Arguments
+/// * `ip_addr` - The address of the server.
+/// * `port` - The port of the server.
+///
+/// # Returns
+/// * `Ok((tcp_stream, socket))` - The connection and the address of the server.
+/// * `Err(e)` - The address is invalid or the server cannot be reached.
+///
+fn open_connection(ip_addr: &str, port: u16) -> Result<(TcpStream, SocketAddrV4), Error> {
+    let ip_addr = ip_addr
+        .parse::<std::net::Ipv4Addr>()
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    let socket = SocketAddrV4::new(ip_addr, port);
+    let tcp_stream = TcpStream::connect(socket)
+        .map_err(|_| Error::new(ErrorKind::Other, "Failed to connect to server"))?;
+    println!("Connected to the server!");
+    let _ = tcp_stream.set_read_timeout(Some(Duration::from_secs(HANDSHAKE_TIMEOUT)));
+    Ok((tcp_stream, socket))
+}
@@ -1,11 +1,24 @@
-use crate::clientstate::ClientState;
+use crate::clientstate::{ChatEntry, ClientState};
 use crate::network;
 use bevy::prelude::*;
 use rand::seq::SliceRandom;
 use rust_scribble_common::gamestate_common::*;
+use rust_scribble_common::handshake_common::{LobbyInfo, LobbyRequest, LobbySummary};
 use rust_scribble_common::messages_common::*;
 use rust_scribble_common::network_common::*;
-use serde_json::json;
+use rust_scribble_common::settings_common::LobbySettings;
+use std::error;
+
+/// How the player wants to find a lobby when connecting.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LobbyChoice {
+    /// Join any public lobby.
+    QuickPlay,
+    /// Open a new private lobby.
+    CreatePrivate,
+    /// Join a private lobby with its join code.
+    JoinCode,
+}
 
 pub struct NetworkState {
     /// client player name
@@ -14,8 +27,20 @@ pub struct NetworkState {
     pub address: String,
     /// client input for server port number to connect to
     pub port: u16,
+    /// client choice how to find a lobby
+    pub lobby_choice: LobbyChoice,
+    /// client input for the name of a new private lobby
+    pub lobby_name: String,
+    /// client input for the join code of a private lobby
+    pub join_code: String,
     // network info if none then not connected
     pub info: Option<NetworkInfo>,
+    /// the lobby the client was placed in by the server
+    pub lobby: Option<LobbyInfo>,
+    /// the public lobbies of the server while the lobby browser is open
+    pub lobby_list: Option<Vec<LobbySummary>>,
+    /// the reason the last connection attempt failed
+    pub connection_error: Option<String>,
 }
 
 impl Default for NetworkState {
@@ -24,7 +49,13 @@ impl Default for NetworkState {
             name: generate_name(),
             address: "127.0.0.1".to_string(),
             port: 3000,
+            lobby_choice: LobbyChoice::QuickPlay,
+            lobby_name: String::new(),
+            join_code: String::new(),
             info: None,
+            lobby: None,
+            lobby_list: None,
+            connection_error: None,
         }
     }
 }
@@ -103,17 +134,64 @@ struct CheckNetworkTimer(Timer);
 /// * `networkstate` - Holding information about the server to connect to
 ///
 pub fn connect(networkstate: &mut ResMut<NetworkState>) {
+    let lobby = match networkstate.lobby_choice {
+        LobbyChoice::QuickPlay => LobbyRequest::Matchmaking,
+        LobbyChoice::CreatePrivate => LobbyRequest::CreatePrivate {
+            name: networkstate.lobby_name.clone(),
+        },
+        LobbyChoice::JoinCode => LobbyRequest::JoinCode {
+            code: networkstate.join_code.clone(),
+        },
+    };
+    join_lobby(networkstate, lobby);
+}
+
+/// Tries to connect to the server and join the given lobby
+///
+/// # Arguments
+/// * `networkstate` - Holding information about the server to connect to
+/// * `lobby` - The lobby to join
+///
+pub fn join_lobby(networkstate: &mut ResMut<NetworkState>, lobby: LobbyRequest) {
     let res = network::connect_to_server(
         networkstate.address.as_str(),
         networkstate.port,
         networkstate.name.as_str(),
+        lobby,
     );
     match res {
-        Ok(info) => {
+        Ok((info, lobby)) => {
             networkstate.info = Some(info);
+            networkstate.lobby = Some(lobby);
+            networkstate.lobby_list = None;
+            networkstate.connection_error = None;
         }
-        Err(_) => {
-            println!("Could not connect to server");
+        Err(e) => {
+            println!("Could not connect to server: {}", e);
+            networkstate.connection_error = Some(e.to_string());
+        }
+    }
+}
+
+/// Fetches the public lobbies of the server and opens the lobby browser
+///
+/// # Arguments
+/// * `networkstate` - Holding information about the server to ask
+///
+pub fn browse_lobbies(networkstate: &mut ResMut<NetworkState>) {
+    let res = network::list_lobbies(
+        networkstate.address.as_str(),
+        networkstate.port,
+        networkstate.name.as_str(),
+    );
+    match res {
+        Ok(lobbies) => {
+            networkstate.lobby_list = Some(lobbies);
+            networkstate.connection_error = None;
+        }
+        Err(e) => {
+            println!("Could not list lobbies: {}", e);
+            networkstate.connection_error = Some(e.to_string());
         }
     }
 }
@@ -126,7 +204,7 @@ pub fn connect(networkstate: &mut ResMut<NetworkState>) {
 ///
 pub fn send_chat_message(networkstate: &mut ResMut<NetworkState>, msg: String) {
     if let Some(network_info) = networkstate.info.as_mut() {
-        let msg = json!(ChatMessage::new(network_info.id, msg));
+        let msg = Message::ChatMessage(ChatMessage::new(network_info.id, msg));
         let _ = send_message(network_info, &msg);
     }
 }
@@ -139,7 +217,7 @@ pub fn send_chat_message(networkstate: &mut ResMut<NetworkState>, msg: String) {
 ///
 pub fn send_ready(networkstate: &mut ResMut<NetworkState>, ready_state: bool) {
     if let Some(network_info) = networkstate.info.as_mut() {
-        let msg = json!(ReadyMessage::new(network_info.id, ready_state));
+        let msg = Message::Ready(ReadyMessage::new(network_info.id, ready_state));
         let _ = send_message(network_info, &msg);
     }
 }
@@ -151,7 +229,7 @@ pub fn send_ready(networkstate: &mut ResMut<NetworkState>, ready_state: bool) {
 ///
 pub fn send_disconnect(networkstate: &mut ResMut<NetworkState>) {
     if let Some(network_info) = networkstate.info.as_mut() {
-        let msg = json!(DisconnectMessage::new(network_info.id));
+        let msg = Message::Disconnect(DisconnectMessage::new(network_info.id));
         let _ = send_message(network_info, &msg);
     }
 }
@@ -164,21 +242,60 @@ pub fn send_disconnect(networkstate: &mut ResMut<NetworkState>) {
 ///
 pub fn send_line(networkstate: &mut ResMut<NetworkState>, line: &Line) {
     if let Some(network_info) = networkstate.info.as_mut() {
-        let msg = json!(PaintingUpdate::new(network_info.id, line.clone()));
+        let msg = Message::AddLine(PaintingUpdate::new(network_info.id, line.clone()));
+        let _ = send_message(network_info, &msg);
+    }
+}
+
+/// Sends new lobby settings to the server, only the host may change them
+///
+/// # Arguments
+/// * `networkstate` - Holding information about the connection to a server
+/// * `settings` - The new settings of the lobby
+///
+pub fn send_settings(networkstate: &mut ResMut<NetworkState>, settings: LobbySettings) {
+    if let Some(network_info) = networkstate.info.as_mut() {
+        let msg = Message::LobbySettingsUpdate(LobbySettingsUpdate::new(network_info.id, settings));
+        let _ = send_message(network_info, &msg);
+    }
+}
+
+/// Tells the server which of the offered words the drawer wants to draw
+///
+/// # Arguments
+/// * `networkstate` - Holding information about the connection to a server
+/// * `choice` - The index of the chosen word in the offered words
+///
+pub fn choose_word(networkstate: &mut ResMut<NetworkState>, choice: usize) {
+    if let Some(network_info) = networkstate.info.as_mut() {
+        let msg = Message::ChooseWord(ChooseWordMessage::new(network_info.id, choice));
+        let _ = send_message(network_info, &msg);
+    }
+}
+
+/// Replaces the custom words of the lobby, only the host may change them
+///
+/// # Arguments
+/// * `networkstate` - Holding information about the connection to a server
+/// * `words` - The custom words, an empty list removes them
+///
+pub fn send_custom_words(networkstate: &mut ResMut<NetworkState>, words: Vec<String>) {
+    if let Some(network_info) = networkstate.info.as_mut() {
+        let msg = Message::CustomWords(CustomWordsMessage::new(network_info.id, words));
         let _ = send_message(network_info, &msg);
     }
 }
 
 pub fn delete_all_lines(networkstate: &mut ResMut<NetworkState>) {
     if let Some(network_info) = networkstate.info.as_mut() {
-        let msg = json!(PaintingUpdate::clear_all(network_info.id));
+        let msg = Message::ClearAllLines(ClearLinesMessage::new(network_info.id));
         let _ = send_message(network_info, &msg);
     }
 }
 
 pub fn delete_last_line(networkstate: &mut ResMut<NetworkState>) {
     if let Some(network_info) = networkstate.info.as_mut() {
-        let msg = json!(PaintingUpdate::clear_last(network_info.id));
+        let msg = Message::ClearLastLine(ClearLinesMessage::new(network_info.id));
         let _ = send_message(network_info, &msg);
     }
 }
@@ -189,38 +306,64 @@ pub fn delete_last_line(networkstate: &mut ResMut<NetworkState>) {
 /// * `network_info` - Holding information about the connection to a server
 /// * `clientstate` - The state of the client holding information about the gamestate, canvas lines, chat messages and players in the game
 ///
-fn handle_messsages(network_info: &mut NetworkInfo, clientstate: &mut ClientState) {
-    if let Ok(msg) = network::read_messages(network_info, 5) {
-        for m in msg {
-            if m["kind"].eq("chat_message") {
-                let message = m["message"].as_str().unwrap();
-                let player_id = m["id"].as_i64().unwrap();
-                let chat_message = ChatMessage::new(player_id, message.to_string());
-                clientstate.chat_messages.push(chat_message);
-            } else if m["kind"].eq("update") {
-                if let Ok(new_gs) = serde_json::from_str(&m["game_state"].to_string()) {
-                    let gs: GameState = new_gs;
-                    if clientstate.game_state.in_game && !gs.in_game {
-                        clientstate.lines.clear();
-                    }
-                    clientstate.game_state = gs;
-                }
-            } else if m["kind"].eq("player_update") {
-                if let Ok(new_gs) = serde_json::from_str(&m["players"].to_string()) {
-                    clientstate.players = new_gs;
-                }
-            } else if m["kind"].eq("add_line") {
-                if let Ok(line) = serde_json::from_str(&m["line"].to_string()) {
-                    let length = clientstate.lines.len();
-                    clientstate.lines.insert(length, line);
+fn handle_messsages(
+    network_info: &mut NetworkInfo,
+    clientstate: &mut ClientState,
+) -> Result<(), Box<dyn error::Error>> {
+    for m in network::read_messages(network_info, 5)? {
+        match m {
+            Message::ChatMessage(chat_message) => {
+                clientstate
+                    .chat_messages
+                    .push(ChatEntry::Chat(chat_message));
+            }
+            Message::Event(event_message) => {
+                clientstate
+                    .chat_messages
+                    .push(ChatEntry::Event(event_message.event));
+            }
+            Message::GameStateUpdate(update) => {
+                let gs: GameState = update.game_state;
+                let old = &clientstate.game_state;
+                // every turn starts with an empty canvas
+                if old.in_game && !gs.in_game || old.round != gs.round || old.turn != gs.turn {
+                    clientstate.lines.clear();
                 }
-            } else if m["kind"].eq("clear_all_lines") {
+                clientstate.game_state = gs;
+            }
+            Message::PlayersUpdate(update) => {
+                clientstate.players = update.players;
+            }
+            Message::AddLine(painting_update) => {
+                clientstate.lines.push(painting_update.line);
+            }
+            Message::ClearAllLines(_) => {
                 clientstate.lines = Vec::new();
-            } else if m["kind"].eq("clear_last_line") {
+            }
+            Message::ClearLastLine(_) => {
                 clientstate.lines.pop();
             }
+            Message::LobbySettingsUpdate(update) => {
+                // keep the unsaved changes of the host, a submitted draft
+                // is confirmed by settings matching it and stops counting as unsaved
+                if clientstate.settings_draft == clientstate.settings {
+                    clientstate.settings_draft = Some(update.settings.clone());
+                }
+                clientstate.settings = Some(update.settings);
+            }
+            // keep-alive only, nothing to do
+            Message::Ping => {}
+            // never sent to clients
+            Message::UserInit(_)
+            | Message::Ready(_)
+            | Message::Disconnect(_)
+            | Message::ChooseWord(_)
+            | Message::CustomWords(_)
+            | Message::TimeUp
+            | Message::UpdateRequested => {}
         }
     }
+    Ok(())
 }
 
 /// the function that is called regularly to check for server messages
@@ -238,8 +381,25 @@ fn update_network(
 ) {
     if timer.0.tick(time.delta()).just_finished() {
         if let Some(network_info) = networkstate.info.as_mut() {
+            let mut connection_error = None;
             if message_waiting(network_info) {
-                handle_messsages(network_info, &mut clientstate)
+                if let Err(e) = handle_messsages(network_info, &mut clientstate) {
+                    // a notice right before the server closed the connection tells why, like a full lobby
+                    connection_error = match clientstate.chat_messages.last() {
+                        Some(ChatEntry::Event(GameEvent::Notice { text })) => {
+                            Some(format!("Disconnected: {}", text))
+                        }
+                        _ => Some(e.to_string()),
+                    };
+                }
+            }
+            // close the connection before any nonce could be used twice
+            if network_info.session.is_exhausted() {
+                connection_error = Some("Session expired, please reconnect".to_string());
+            }
+            if connection_error.is_some() {
+                networkstate.info = None;
+                networkstate.connection_error = connection_error;
             }
         }
     }
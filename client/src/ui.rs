@@ -2,11 +2,31 @@ use bevy::prelude::*;
 use bevy_egui::{egui, EguiContext};
 use egui::{vec2, Color32, RichText, Stroke};
 use rayon::prelude::*;
-use regex::Regex;
 
-use crate::clientstate::ClientState;
+use crate::clientstate::{ChatEntry, ClientState};
+use crate::network_plugin::LobbyChoice;
 use crate::{network_plugin, Textures};
 use rust_scribble_common::gamestate_common::*;
+use rust_scribble_common::handshake_common::LobbyRequest;
+use rust_scribble_common::messages_common::{ChatChannel, GameEvent, MAX_CHAT_MESSAGE_LENGTH};
+use rust_scribble_common::settings_common::*;
+
+/// Text colour of the chat only the drawer and the players who guessed the word can read.
+const GUESSED_CHAT_COLOR: Color32 = Color32::from_rgb(0, 110, 60);
+/// Background of the chat only the drawer and the players who guessed the word can read.
+const GUESSED_CHAT_BACKGROUND: Color32 = Color32::from_rgb(214, 240, 214);
+/// Text colour of players joining and leaving the lobby.
+const PLAYERS_EVENT_COLOR: Color32 = Color32::from_rgb(90, 90, 90);
+/// Text colour of a correct guess.
+const CORRECT_EVENT_COLOR: Color32 = Color32::from_rgb(0, 140, 40);
+/// Text colour of a guess that was almost the word.
+const CLOSE_EVENT_COLOR: Color32 = Color32::from_rgb(210, 110, 0);
+/// Text colour of rounds starting and ending.
+const ROUND_EVENT_COLOR: Color32 = Color32::from_rgb(30, 70, 200);
+/// Text colour of the revealed word.
+const WORD_EVENT_COLOR: Color32 = Color32::from_rgb(130, 40, 170);
+/// Text colour of the notices of the server.
+const NOTICE_EVENT_COLOR: Color32 = Color32::from_rgb(190, 30, 30);
 
 /// this system handles rendering the ui
 ///
@@ -21,7 +41,9 @@ pub fn render_ui(
     mut clientstate: ResMut<ClientState>,
     textures: Res<Textures>,
 ) {
-    if networkstate.info.is_none() {
+    if networkstate.info.is_none() && networkstate.lobby_list.is_some() {
+        render_lobby_browser(&mut egui_context, &mut networkstate);
+    } else if networkstate.info.is_none() {
         render_connect_view(&mut egui_context, &mut networkstate, &textures);
     } else if clientstate.game_state.in_game {
         render_ingame_view(&mut egui_context, &mut networkstate, &mut clientstate);
@@ -55,6 +77,8 @@ fn render_connect_view(
             ui.add_space(20.0);
             ui.label("Server Port");
             ui.add(egui::widgets::DragValue::new(&mut networkstate.port).speed(1.0));
+            ui.add_space(20.0);
+            render_lobby_choice(ui, networkstate);
             ui.add_space(40.0);
             if ui
                 .button(
@@ -68,10 +92,123 @@ fn render_connect_view(
                 // connect to the server
                 network_plugin::connect(networkstate);
             }
+            ui.add_space(10.0);
+            if ui.button("Browse Lobbies").clicked() {
+                network_plugin::browse_lobbies(networkstate);
+            }
+            if let Some(error) = &networkstate.connection_error {
+                ui.add_space(20.0);
+                ui.label(RichText::new(error).color(Color32::RED));
+            }
         });
     });
 }
 
+/// renders the list of public lobbies to pick one to join
+///
+/// # Arguments
+/// * `egui_context` - The egui context used for rendering the egui
+/// * `networkstate` - Holding the list of lobbies and information about the server
+///
+fn render_lobby_browser(
+    egui_context: &mut ResMut<EguiContext>,
+    networkstate: &mut ResMut<network_plugin::NetworkState>,
+) {
+    let lobbies = networkstate.lobby_list.clone().unwrap_or_default();
+    egui::CentralPanel::default().show(egui_context.ctx_mut(), |ui| {
+        ui.vertical_centered(|ui| {
+            ui.add_space(50.0);
+            ui.heading(RichText::new("Lobbies").size(36.0));
+            ui.add_space(20.0);
+            ui.horizontal(|ui| {
+                if ui.button("Back").clicked() {
+                    networkstate.lobby_list = None;
+                }
+                if ui.button("Refresh").clicked() {
+                    network_plugin::browse_lobbies(networkstate);
+                }
+            });
+            ui.add_space(20.0);
+            if lobbies.is_empty() {
+                ui.label("There are no public lobbies yet, use quick play to open one.");
+            }
+            egui::Grid::new("lobby_browser")
+                .striped(true)
+                .spacing(vec2(30.0, 10.0))
+                .show(ui, |ui| {
+                    if !lobbies.is_empty() {
+                        ui.label(RichText::new("Name").strong());
+                        ui.label(RichText::new("Players").strong());
+                        ui.label(RichText::new("Status").strong());
+                        ui.label(RichText::new("Language").strong());
+                        ui.label(RichText::new("Words").strong());
+                        ui.end_row();
+                    }
+                    for lobby in &lobbies {
+                        ui.label(&lobby.name);
+                        ui.label(format!("{}/{}", lobby.players, lobby.capacity));
+                        ui.label(if lobby.in_game { "In game" } else { "Waiting" });
+                        ui.label(lobby.languages.join(", "));
+                        ui.label(lobby.word_packs.join(", "));
+                        let has_room = lobby.players < lobby.capacity;
+                        if ui
+                            .add_enabled(has_room, egui::Button::new("Join"))
+                            .clicked()
+                        {
+                            network_plugin::join_lobby(
+                                networkstate,
+                                LobbyRequest::Lobby { id: lobby.id },
+                            );
+                        }
+                        ui.end_row();
+                    }
+                });
+            if let Some(error) = &networkstate.connection_error {
+                ui.add_space(20.0);
+                ui.label(RichText::new(error).color(Color32::RED));
+            }
+        });
+    });
+}
+
+/// renders the choice how to find a lobby on the connect view
+///
+/// # Arguments
+/// * `ui` - The ui to render the choice on
+/// * `networkstate` - Holding the lobby choice and its inputs
+///
+fn render_lobby_choice(ui: &mut egui::Ui, networkstate: &mut ResMut<network_plugin::NetworkState>) {
+    ui.label("Lobby");
+    ui.horizontal(|ui| {
+        ui.radio_value(
+            &mut networkstate.lobby_choice,
+            LobbyChoice::QuickPlay,
+            "Quick play",
+        );
+        ui.radio_value(
+            &mut networkstate.lobby_choice,
+            LobbyChoice::CreatePrivate,
+            "Create private lobby",
+        );
+        ui.radio_value(
+            &mut networkstate.lobby_choice,
+            LobbyChoice::JoinCode,
+            "Join with code",
+        );
+    });
+    match networkstate.lobby_choice {
+        LobbyChoice::QuickPlay => {}
+        LobbyChoice::CreatePrivate => {
+            ui.label("Lobby Name");
+            ui.text_edit_singleline(&mut networkstate.lobby_name);
+        }
+        LobbyChoice::JoinCode => {
+            ui.label("Join Code");
+            ui.text_edit_singleline(&mut networkstate.join_code);
+        }
+    }
+}
+
 /// renders the view when connected to a server and in the lobby
 ///
 /// # Arguments
@@ -93,13 +230,27 @@ fn render_lobby_view(
         });
 
     egui::CentralPanel::default().show(egui_context.ctx_mut(), |ui| {
-        ui.label(egui::RichText::new("Lobby").font(egui::FontId::proportional(40.0)));
+        let lobby_name = match &networkstate.lobby {
+            Some(lobby) => lobby.name.clone(),
+            None => "Lobby".to_string(),
+        };
+        ui.label(egui::RichText::new(lobby_name).font(egui::FontId::proportional(40.0)));
+        if let Some(join_code) = networkstate
+            .lobby
+            .as_ref()
+            .and_then(|lobby| lobby.join_code.clone())
+        {
+            ui.label(RichText::new(format!("Join code: {}", join_code)).monospace());
+            ui.label("Share this code with your friends so they can join.");
+        }
+        let mut is_host = false;
         if let Some(net_info) = networkstate.info.as_mut() {
             let player_result = clientstate
                 .players
                 .iter()
                 .find(|player| player.id == net_info.id);
             if let Some(player) = player_result {
+                is_host = player.host;
                 if player.ready {
                     if ui.button("Not Ready").clicked() {
                         network_plugin::send_ready(networkstate, false);
@@ -109,9 +260,241 @@ fn render_lobby_view(
                 }
             }
         }
+        if clientstate.game_state.phase == GamePhase::MatchOver {
+            ui.add_space(20.0);
+            render_final_scores(ui, clientstate);
+        }
+        ui.add_space(20.0);
+        render_lobby_settings(ui, networkstate, clientstate, is_host);
     });
 }
 
+/// renders the scoreboard of the last match
+///
+/// # Arguments
+/// * `ui` - The ui to render the scoreboard on
+/// * `clientstate` - Holding the final scores of the match
+///
+fn render_final_scores(ui: &mut egui::Ui, clientstate: &mut ResMut<ClientState>) {
+    ui.heading("Final Scores");
+    egui::Grid::new("final_scores")
+        .striped(true)
+        .spacing(vec2(30.0, 8.0))
+        .show(ui, |ui| {
+            for (place, score) in clientstate.game_state.final_scores.iter().enumerate() {
+                ui.label(format!("{}.", place + 1));
+                ui.label(&score.name);
+                ui.label(score.score.to_string());
+                ui.end_row();
+            }
+        });
+}
+
+/// renders the settings of the lobby, the host can edit and apply them
+///
+/// # Arguments
+/// * `ui` - The ui to render the settings on
+/// * `networkstate` - Holding information about the connection to a server
+/// * `clientstate` - Holding the settings of the lobby and the draft of the host
+/// * `is_host` - Whether this client may change the settings
+///
+fn render_lobby_settings(
+    ui: &mut egui::Ui,
+    networkstate: &mut ResMut<network_plugin::NetworkState>,
+    clientstate: &mut ResMut<ClientState>,
+    is_host: bool,
+) {
+    let word_packs = networkstate
+        .lobby
+        .as_ref()
+        .map_or_else(Vec::new, |lobby| lobby.word_packs.clone());
+    let settings = match clientstate.settings_draft.as_mut() {
+        Some(settings) => settings,
+        None => return,
+    };
+    ui.heading("Settings");
+    ui.add_enabled_ui(is_host, |ui| {
+        egui::Grid::new("lobby_settings")
+            .spacing(vec2(20.0, 8.0))
+            .show(ui, |ui| {
+                ui.label("Round time");
+                ui.add(
+                    egui::DragValue::new(&mut settings.round_time)
+                        .clamp_range(ROUND_TIME_RANGE)
+                        .suffix(" s"),
+                );
+                ui.end_row();
+                ui.label("Max players");
+                ui.add(
+                    egui::DragValue::new(&mut settings.max_players).clamp_range(MAX_PLAYERS_RANGE),
+                );
+                ui.end_row();
+                ui.label("Rounds");
+                ui.add(egui::DragValue::new(&mut settings.rounds).clamp_range(ROUNDS_RANGE));
+                ui.end_row();
+                ui.label("Word choices");
+                ui.add(
+                    egui::DragValue::new(&mut settings.word_choices)
+                        .clamp_range(WORD_CHOICES_RANGE),
+                );
+                ui.end_row();
+                ui.label("Word packs");
+                ui.vertical(|ui| {
+                    for word_pack in word_packs {
+                        let mut chosen = settings.word_packs.contains(&word_pack.name);
+                        let label = format!("{} ({})", word_pack.name, word_pack.language);
+                        let response = ui.checkbox(&mut chosen, label).on_hover_text(format!(
+                            "{} words: {}",
+                            word_pack.words,
+                            word_pack.categories.join(", ")
+                        ));
+                        if response.changed() {
+                            toggle(&mut settings.word_packs, word_pack.name.clone(), chosen);
+                        }
+                    }
+                });
+                ui.end_row();
+                ui.label("Difficulties");
+                ui.horizontal(|ui| {
+                    for difficulty in [Difficulty::Easy, Difficulty::Medium, Difficulty::Hard] {
+                        let mut chosen = settings.difficulties.contains(&difficulty);
+                        if ui
+                            .checkbox(&mut chosen, format!("{:?}", difficulty))
+                            .changed()
+                        {
+                            toggle(&mut settings.difficulties, difficulty, chosen);
+                        }
+                    }
+                });
+                ui.end_row();
+                ui.label("Custom words");
+                ui.checkbox(&mut settings.only_custom_words, "Play only custom words");
+                ui.end_row();
+                ui.label("Also accept");
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut settings.accept_plurals, "Plurals");
+                    ui.checkbox(&mut settings.accept_aliases, "Other spellings");
+                });
+                ui.end_row();
+                ui.label("Hints");
+                egui::ComboBox::from_id_source("hint_schedule")
+                    .selected_text(format!("{:?}", settings.hint_schedule))
+                    .show_ui(ui, |ui| {
+                        for schedule in [
+                            HintSchedule::Off,
+                            HintSchedule::Slow,
+                            HintSchedule::Normal,
+                            HintSchedule::Fast,
+                        ] {
+                            ui.selectable_value(
+                                &mut settings.hint_schedule,
+                                schedule,
+                                format!("{:?}", schedule),
+                            );
+                        }
+                    });
+                ui.end_row();
+                ui.label("Guesser scoring");
+                egui::ComboBox::from_id_source("guesser_scoring")
+                    .selected_text(format!("{:?}", settings.guesser_scoring))
+                    .show_ui(ui, |ui| {
+                        for scoring in [
+                            GuesserScoring::Equal,
+                            GuesserScoring::TimeBased,
+                            GuesserScoring::LinearlyDecreasing,
+                            GuesserScoring::ExponentiallyDecreasing,
+                        ] {
+                            ui.selectable_value(
+                                &mut settings.guesser_scoring,
+                                scoring,
+                                format!("{:?}", scoring),
+                            );
+                        }
+                    });
+                ui.end_row();
+                ui.label("Drawer scoring");
+                egui::ComboBox::from_id_source("drawer_scoring")
+                    .selected_text(format!("{:?}", settings.drawer_scoring))
+                    .show_ui(ui, |ui| {
+                        for scoring in [
+                            DrawerScoring::Equal,
+                            DrawerScoring::TimeBased,
+                            DrawerScoring::ExponentiallyIncreasing,
+                        ] {
+                            ui.selectable_value(
+                                &mut settings.drawer_scoring,
+                                scoring,
+                                format!("{:?}", scoring),
+                            );
+                        }
+                    });
+                ui.end_row();
+            });
+    });
+    if is_host && clientstate.settings_draft != clientstate.settings {
+        if ui.button("Apply Settings").clicked() {
+            let settings = clientstate.settings_draft.clone().unwrap();
+            network_plugin::send_settings(networkstate, settings);
+        }
+        if ui.button("Discard Changes").clicked() {
+            clientstate.settings_draft = clientstate.settings.clone();
+        }
+    }
+    if is_host {
+        ui.add_space(20.0);
+        render_custom_words(ui, networkstate, clientstate);
+    }
+}
+
+/// renders the input of the host for the custom words of the lobby
+///
+/// # Arguments
+/// * `ui` - The ui to render the input on
+/// * `networkstate` - Holding information about the connection to a server
+/// * `clientstate` - Holding the custom words the host is typing
+///
+fn render_custom_words(
+    ui: &mut egui::Ui,
+    networkstate: &mut ResMut<network_plugin::NetworkState>,
+    clientstate: &mut ResMut<ClientState>,
+) {
+    ui.heading("Custom Words");
+    ui.label(format!(
+        "Up to {} words, separated by commas or new lines. Leave empty to remove the custom words.",
+        MAX_CUSTOM_WORDS
+    ));
+    ui.add(
+        egui::TextEdit::multiline(&mut clientstate.custom_words_input)
+            .hint_text("inside joke, another word")
+            .desired_rows(4),
+    );
+    if ui.button("Upload Words").clicked() {
+        let words = clientstate
+            .custom_words_input
+            .split([',', '\n'])
+            .map(|word| word.to_string())
+            .collect();
+        network_plugin::send_custom_words(networkstate, words);
+    }
+}
+
+/// adds a value to a list of chosen values or removes it
+///
+/// # Arguments
+/// * `values` - The chosen values
+/// * `value` - The value that was checked or unchecked
+/// * `chosen` - Whether the value is chosen now
+///
+fn toggle<T: PartialEq>(values: &mut Vec<T>, value: T, chosen: bool) {
+    if chosen {
+        if !values.contains(&value) {
+            values.push(value);
+        }
+    } else {
+        values.retain(|other| *other != value);
+    }
+}
+
 /// renders the view when connected to a server and playing the game
 ///
 /// # Arguments
@@ -153,6 +536,13 @@ fn render_ingame_view(
         is_drawer = player.drawing;
         has_guessed = player.guessed_word;
     }
+    let choosing_word = clientstate.game_state.phase == GamePhase::ChoosingWord;
+    if is_drawer && choosing_word {
+        render_word_choice(egui_context, networkstate, clientstate);
+    }
+    if clientstate.game_state.phase == GamePhase::TurnOver {
+        render_turn_summary(egui_context, clientstate);
+    }
 
     // The central panel the region left after adding TopPanel's and SidePanel's
     egui::CentralPanel::default().show(egui_context.ctx_mut(), |ui| {
@@ -224,13 +614,23 @@ fn render_ingame_view(
                         .font(egui::FontId::proportional(40.0)),
                 );
             });
+        } else if choosing_word {
+            let drawer = clientstate
+                .players
+                .iter()
+                .find(|player| player.drawing)
+                .map_or_else(String::new, |player| player.name.clone());
+            ui.label(
+                egui::RichText::new(format!("{} is choosing a word...", drawer))
+                    .font(egui::FontId::proportional(40.0)),
+            );
         } else {
             ui.label("Guess the word!");
             if !has_guessed {
                 ui.label(
                     egui::RichText::new(format!(
                         "Word: {}",
-                        get_word_as_underscores(&clientstate.game_state.word)
+                        get_word_hint(&clientstate.game_state.hint)
                     ))
                     .font(egui::FontId::proportional(40.0)),
                 );
@@ -321,6 +721,129 @@ fn render_ingame_view(
     });
 }
 
+/// renders the words the drawer can choose from on top of the canvas, only shown to the drawer
+///
+/// # Arguments
+/// * `egui_context` - The egui context used for rendering the egui
+/// * `networkstate` - Holding information about the connection to a server
+/// * `clientstate` - The state of the client holding information about the gamestate, canvas lines, chat messages and players in the game
+///
+fn render_word_choice(
+    egui_context: &mut ResMut<EguiContext>,
+    networkstate: &mut ResMut<network_plugin::NetworkState>,
+    clientstate: &mut ResMut<ClientState>,
+) {
+    egui::Window::new("Choose a word")
+        .anchor(egui::Align2::CENTER_CENTER, vec2(0.0, 0.0))
+        .collapsible(false)
+        .resizable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.label(format!(
+                "A word is picked for you in {}s",
+                clientstate.game_state.time
+            ));
+            ui.horizontal(|ui| {
+                for (choice, word) in clientstate.game_state.word_choices.iter().enumerate() {
+                    let button = egui::Button::new(
+                        RichText::new(word).font(egui::FontId::proportional(30.0)),
+                    );
+                    if ui.add(button).clicked() {
+                        network_plugin::choose_word(networkstate, choice);
+                    }
+                }
+            });
+        });
+}
+
+/// renders the word and the points of the turn that just ended on top of the canvas
+///
+/// # Arguments
+/// * `egui_context` - The egui context used for rendering the egui
+/// * `clientstate` - Holding the word and the summary of the turn
+///
+fn render_turn_summary(
+    egui_context: &mut ResMut<EguiContext>,
+    clientstate: &mut ResMut<ClientState>,
+) {
+    let game_state = &clientstate.game_state;
+    let summary = match game_state.turn_summary.as_ref() {
+        Some(summary) => summary,
+        None => return,
+    };
+    egui::Window::new("Turn over")
+        .anchor(egui::Align2::CENTER_CENTER, vec2(0.0, 0.0))
+        .collapsible(false)
+        .resizable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.label(
+                RichText::new(format!("The word was \"{}\"", game_state.word))
+                    .font(egui::FontId::proportional(30.0)),
+            );
+            ui.add_space(10.0);
+            if summary.guesses.is_empty() {
+                ui.label("Nobody guessed the word.");
+            }
+            egui::Grid::new("turn_summary")
+                .striped(true)
+                .spacing(vec2(30.0, 8.0))
+                .show(ui, |ui| {
+                    for (place, guess) in summary.guesses.iter().enumerate() {
+                        ui.label(format!("{}.", place + 1));
+                        ui.label(&guess.name);
+                        ui.label(format!("+{}", guess.points));
+                        ui.end_row();
+                    }
+                    if let Some(drawer) = summary.drawer.as_ref() {
+                        ui.label("Drawer");
+                        ui.label(&drawer.name);
+                        ui.label(format!("+{}", drawer.points));
+                        ui.end_row();
+                    }
+                });
+            ui.add_space(10.0);
+            ui.label(format!("Next turn in {}s", game_state.time));
+        });
+}
+
+/// describes an event of the lobby for the chat, every kind of event has its own style
+///
+/// # Arguments
+/// * `event` - The event to describe
+///
+/// # Returns
+/// * `RichText` - The styled line of the chat
+///
+fn event_text(event: &GameEvent) -> RichText {
+    match event {
+        GameEvent::PlayerJoined { name } => {
+            RichText::new(format!("{} joined the lobby", name)).color(PLAYERS_EVENT_COLOR)
+        }
+        GameEvent::PlayerLeft { name } => {
+            RichText::new(format!("{} left the lobby", name)).color(PLAYERS_EVENT_COLOR)
+        }
+        GameEvent::CorrectGuess { name, .. } => {
+            RichText::new(format!("{} guessed the word!", name))
+                .color(CORRECT_EVENT_COLOR)
+                .strong()
+        }
+        GameEvent::CloseGuess { guess } => {
+            RichText::new(format!("\"{}\" is close!", guess)).color(CLOSE_EVENT_COLOR)
+        }
+        GameEvent::RoundStarted { round, rounds } => {
+            RichText::new(format!("Round {} of {} started", round, rounds))
+                .color(ROUND_EVENT_COLOR)
+                .strong()
+        }
+        GameEvent::RoundEnded { round } => {
+            RichText::new(format!("Round {} ended", round)).color(ROUND_EVENT_COLOR)
+        }
+        GameEvent::WordRevealed { word } => RichText::new(format!("The word was \"{}\"", word))
+            .color(WORD_EVENT_COLOR)
+            .strong(),
+        GameEvent::Notice { text } => RichText::new(text).color(NOTICE_EVENT_COLOR).italics(),
+    }
+}
+
 /// renders a chat area with chat history and message input
 ///
 /// # Arguments
@@ -346,7 +869,14 @@ fn render_chat_area(
             .stick_to_bottom()
             .max_height(200.0)
             .show_rows(ui, row_height, 100, |ui, _| {
-                for chat_message in clientstate.chat_messages.iter() {
+                for entry in clientstate.chat_messages.iter() {
+                    let chat_message = match entry {
+                        ChatEntry::Chat(chat_message) => chat_message,
+                        ChatEntry::Event(event) => {
+                            ui.label(event_text(event));
+                            continue;
+                        }
+                    };
                     let search_player_result = clientstate
                         .players
                         .par_iter()
@@ -358,15 +888,50 @@ fn render_chat_area(
                                     .color(player.color)
                                     .monospace(),
                             );
-                            ui.label(chat_message.message.to_string());
+                            match chat_message.channel {
+                                ChatChannel::Everyone => {
+                                    ui.label(chat_message.message.to_string());
+                                }
+                                // only the drawer and the players who guessed the word can read these
+                                ChatChannel::Guessed => {
+                                    ui.label(
+                                        RichText::new(chat_message.message.to_string())
+                                            .italics()
+                                            .color(GUESSED_CHAT_COLOR)
+                                            .background_color(GUESSED_CHAT_BACKGROUND),
+                                    );
+                                }
+                            }
                         });
                         ui.set_min_width(100.0);
                     }
                 }
             });
+        // while the word is drawn the drawer and the players who guessed it only reach each other
+        let my_role = networkstate.info.as_ref().and_then(|info| {
+            clientstate
+                .players
+                .iter()
+                .find(|player| player.id == info.id)
+                .map(|player| player.role())
+        });
+        let in_guessed_channel = clientstate.game_state.phase == GamePhase::Drawing
+            && matches!(my_role, Some(role) if role.knows_word());
         ui.horizontal(|ui| {
-            ui.label("Chat: ");
+            if in_guessed_channel {
+                ui.label(RichText::new("Guessed chat: ").color(GUESSED_CHAT_COLOR));
+            } else {
+                ui.label("Chat: ");
+            }
             ui.text_edit_singleline(&mut clientstate.chat_message_input);
+            // the server drops longer messages
+            if clientstate.chat_message_input.chars().count() > MAX_CHAT_MESSAGE_LENGTH {
+                clientstate.chat_message_input = clientstate
+                    .chat_message_input
+                    .chars()
+                    .take(MAX_CHAT_MESSAGE_LENGTH)
+                    .collect();
+            }
             if ui.button("Send").clicked()
                 || (ui.input().key_pressed(egui::Key::Enter)
                     && !clientstate.chat_message_input.is_empty())
@@ -388,8 +953,14 @@ fn render_chat_area(
 /// * `clientstate` - The state of the client holding information about the gamestate, canvas lines, chat messages and players in the game
 ///
 fn render_game_time(ui: &mut egui::Ui, clientstate: &mut ResMut<ClientState>) {
+    let game_state = &clientstate.game_state;
     ui.group(|ui| {
-        ui.label(format!("Time: {}s", clientstate.game_state.time));
+        ui.label(format!(
+            "Round {} of {}",
+            game_state.round, game_state.rounds
+        ));
+        ui.label(format!("Turn {} of {}", game_state.turn, game_state.turns));
+        ui.label(format!("Time: {}s", game_state.time));
     });
 }
 
@@ -475,18 +1046,27 @@ fn get_player_name_with_you(
     player: &Player,
 ) -> std::string::String {
     let net_info = networkstate.info.as_ref().unwrap();
+    let mut name = player.name.to_string();
+    if player.host {
+        name.push_str(" (Host)");
+    }
     if net_info.id == player.id {
-        return format!("{} (You)", player.name);
+        name.push_str(" (You)");
     }
-    player.name.to_string()
+    name
 }
 
-/// returns a word with all letters replaced for underscores
+/// returns the hint of a word with all hidden letters rendered as underscores
 ///
 /// # Arguments
-/// * `word` - The word to render as underscores
+/// * `hint` - The revealed letters of the word, `None` for hidden letters
 ///
-fn get_word_as_underscores(word: &std::string::String) -> std::string::String {
-    let re = Regex::new(r"[A-Za-z]").unwrap();
-    re.replace_all(&word.to_string(), " _ ").to_string()
+fn get_word_hint(hint: &[Option<char>]) -> std::string::String {
+    hint.iter()
+        .map(|letter| match letter {
+            Some(c) if c.is_alphanumeric() => format!(" {} ", c),
+            Some(c) => c.to_string(),
+            None => " _ ".to_string(),
+        })
+        .collect()
 }
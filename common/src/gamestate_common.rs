@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 use egui::{Color32, Pos2, Stroke};
-use random_color::{RandomColor, Luminosity::Bright};
+use random_color::{Luminosity::Bright, RandomColor};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Line {
@@ -25,6 +25,8 @@ pub struct Player {
     pub playing: bool,
     /// has player guessed the word?
     pub guessed_word: bool,
+    /// may the player change the settings of the lobby?
+    pub host: bool,
     /// individual color for each player for gui
     pub color: Color32,
 }
@@ -40,19 +42,111 @@ impl Player {
             drawing: false,
             playing: false,
             guessed_word: false,
+            host: false,
             color: Color32::from_rgb(player_color[0], player_color[1], player_color[2]),
         }
     }
+
+    /// The role of the player in the current round, which decides how much of the game state they may see.
+    pub fn role(&self) -> PlayerRole {
+        if !self.playing {
+            PlayerRole::Spectator
+        } else if self.drawing {
+            PlayerRole::Drawer
+        } else if self.guessed_word {
+            PlayerRole::Guessed
+        } else {
+            PlayerRole::Guesser
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+pub enum PlayerRole {
+    /// draws the word and knows it
+    Drawer,
+    /// still has to guess the word
+    Guesser,
+    /// has already guessed the word
+    Guessed,
+    /// is not taking part in the current round
+    Spectator,
+}
+
+impl PlayerRole {
+    /// Whether the player knows the word of the turn and may read the chat of the others who know it.
+    pub fn knows_word(&self) -> bool {
+        matches!(self, PlayerRole::Drawer | PlayerRole::Guessed)
+    }
+}
+
+/// The part of a match a lobby is in.
+#[derive(Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum GamePhase {
+    /// waiting for the players to get ready
+    Lobby,
+    /// the drawer chooses the word of the turn
+    ChoosingWord,
+    /// a turn is running, one player draws and the others guess
+    Drawing,
+    /// the turn is over, its word and points are shown to everybody until the next turn starts
+    TurnOver,
+    /// the match is over and the final scores are shown until the next match starts
+    MatchOver,
+}
+
+/// The score of a player at the end of a match.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FinalScore {
+    pub id: i64,
+    pub name: String,
+    pub score: i64,
+}
+
+/// The points a player earned in a turn.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TurnScore {
+    pub id: i64,
+    pub name: String,
+    pub points: i64,
+}
+
+/// What happened in the turn that just ended.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TurnSummary {
+    /// the players who guessed the word, the first one first
+    pub guesses: Vec<TurnScore>,
+    /// the points of the drawer, none if the drawer left during the turn
+    pub drawer: Option<TurnScore>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct GameState {
     /// are we in lobby or ingame?
     pub in_game: bool,
+    /// the part of the match the lobby is in
+    pub phase: GamePhase,
+    /// the current round of the match, starting at 1
+    pub round: u32,
+    /// the number of rounds in the match
+    pub rounds: u32,
+    /// the current turn of the round, starting at 1
+    pub turn: usize,
+    /// the number of turns in a round, one for every player drawing in the match
+    pub turns: usize,
+    /// the scores of the last match, best first
+    pub final_scores: Vec<FinalScore>,
+    /// the summary of the turn that just ended, only set while the turn is over
+    pub turn_summary: Option<TurnSummary>,
+    /// the words the drawer can choose from at the start of a turn (only visible to drawer)
+    pub word_choices: Vec<String>,
     /// the word that has to be drawn (only visible to drawer)
     pub word: String,
     /// The length of the word
     pub word_length: i64,
+    /// the letters of the word revealed so far, `None` for letters that are still hidden
+    pub hint: Vec<Option<char>>,
     /// remaining time for round in seconds
     pub time: i64,
 }
@@ -61,9 +155,66 @@ impl GameState {
     pub fn default() -> GameState {
         GameState {
             in_game: false,
+            phase: GamePhase::Lobby,
+            round: 0,
+            rounds: 0,
+            turn: 0,
+            turns: 0,
+            final_scores: Vec::new(),
+            turn_summary: None,
+            word_choices: Vec::new(),
             word: "".to_string(),
             word_length: 0,
+            hint: Vec::new(),
             time: 0,
         }
     }
+
+    /// The number of letters of the word revealed to the guessers so far.
+    pub fn hints_revealed(&self) -> usize {
+        self.hint
+            .iter()
+            .filter(|c| matches!(c, Some(c) if c.is_alphanumeric()))
+            .count()
+    }
+
+    /// Creates the copy of the game state a player with the given role is allowed to see.
+    ///
+    /// Only the drawer and players who already guessed it receive the word,
+    /// everybody else has to make do with `word_length` and `hint` until the turn is over.
+    /// The words the drawer can choose from are only sent to the drawer.
+    ///
+    /// # Arguments
+    /// * `role` - The role of the player receiving the game state.
+    pub fn view_for(&self, role: PlayerRole) -> GameState {
+        match role {
+            PlayerRole::Drawer => self.clone(),
+            PlayerRole::Guessed => GameState {
+                word_choices: Vec::new(),
+                ..self.clone()
+            },
+            PlayerRole::Guesser | PlayerRole::Spectator if self.phase == GamePhase::TurnOver => {
+                GameState {
+                    word_choices: Vec::new(),
+                    ..self.clone()
+                }
+            }
+            PlayerRole::Guesser | PlayerRole::Spectator => GameState {
+                word_choices: Vec::new(),
+                word: String::new(),
+                ..self.clone()
+            },
+        }
+    }
+}
+
+/// Creates the hint for a word with no letters revealed yet.
+/// Spaces and punctuation are not part of the puzzle and are always shown.
+///
+/// # Arguments
+/// * `word` - The word to create the hint for.
+pub fn hidden_hint(word: &str) -> Vec<Option<char>> {
+    word.chars()
+        .map(|c| if c.is_alphanumeric() { None } else { Some(c) })
+        .collect()
 }
@@ -0,0 +1,324 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::network_common::PROTOCOL_VERSION;
+use crate::settings_common::WordPackInfo;
+
+/// Longest username in characters a server accepts.
+pub const MAX_USERNAME_LENGTH: usize = 24;
+/// Longest lobby name in characters a server accepts.
+pub const MAX_LOBBY_NAME_LENGTH: usize = 32;
+
+/// How the messages of a connection are protected after the handshake.
+#[derive(Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EncryptionMode {
+    /// Messages are encrypted and authenticated with the session keys.
+    Encrypted = 0,
+    /// Messages are sent in plaintext, only the checksum and counter are checked.
+    Plaintext = 1,
+}
+
+impl EncryptionMode {
+    /// The encryption modes a client offers and a server accepts unless configured otherwise,
+    /// in order of preference.
+    ///
+    /// Builds with the `no-encryption` feature prefer plaintext but still speak to encrypted peers.
+    pub fn default_modes() -> Vec<EncryptionMode> {
+        if cfg!(feature = "no-encryption") {
+            vec![EncryptionMode::Plaintext, EncryptionMode::Encrypted]
+        } else {
+            vec![EncryptionMode::Encrypted]
+        }
+    }
+}
+
+impl fmt::Display for EncryptionMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncryptionMode::Encrypted => write!(f, "encrypted"),
+            EncryptionMode::Plaintext => write!(f, "plaintext"),
+        }
+    }
+}
+
+/// The first message of a connection, sent by the client before any key is exchanged.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ClientHello {
+    /// The protocol version the client speaks.
+    pub protocol_version: u8,
+    /// The encryption modes the client supports, in order of preference.
+    pub encryption_modes: Vec<EncryptionMode>,
+    /// The name of the player.
+    pub username: String,
+    /// The version of the client program.
+    pub client_version: String,
+    /// The lobby the player would like to join.
+    pub lobby: LobbyRequest,
+}
+
+impl ClientHello {
+    pub fn new(
+        encryption_modes: Vec<EncryptionMode>,
+        username: String,
+        client_version: String,
+        lobby: LobbyRequest,
+    ) -> Self {
+        ClientHello {
+            protocol_version: PROTOCOL_VERSION,
+            encryption_modes,
+            username,
+            client_version,
+            lobby,
+        }
+    }
+}
+
+/// The lobby a player asks to join in the handshake.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LobbyRequest {
+    /// Any public lobby with free places, a new one is opened if all are full.
+    Matchmaking,
+    /// The public lobby with the given id.
+    Lobby { id: u64 },
+    /// A new private lobby that can only be joined with its join code.
+    /// An empty name is replaced by a name derived from the username.
+    CreatePrivate { name: String },
+    /// The private lobby with the given join code.
+    JoinCode { code: String },
+    /// No lobby, the server answers with the list of public lobbies and closes the connection.
+    Browse,
+}
+
+/// The lobby a player was placed in.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LobbyInfo {
+    pub id: u64,
+    pub name: String,
+    /// The code other players need to join a private lobby, public lobbies have none.
+    pub join_code: Option<String>,
+    /// The word packs the host can choose from.
+    pub word_packs: Vec<WordPackInfo>,
+}
+
+impl LobbyInfo {
+    pub fn new(
+        id: u64,
+        name: String,
+        join_code: Option<String>,
+        word_packs: Vec<WordPackInfo>,
+    ) -> Self {
+        LobbyInfo {
+            id,
+            name,
+            join_code,
+            word_packs,
+        }
+    }
+}
+
+/// A public lobby as shown in the lobby browser.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LobbySummary {
+    pub id: u64,
+    pub name: String,
+    /// The number of players in the lobby.
+    pub players: usize,
+    /// The number of players the lobby can hold.
+    pub capacity: usize,
+    /// Whether a game is running or the lobby is waiting for players to get ready.
+    pub in_game: bool,
+    /// The languages of the words.
+    pub languages: Vec<String>,
+    /// The names of the word packs the lobby plays with.
+    pub word_packs: Vec<String>,
+}
+
+/// The answer of the server to a ClientHello.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum ServerHello {
+    /// The server continues with the key exchange using the chosen encryption mode.
+    Accept {
+        protocol_version: u8,
+        encryption_mode: EncryptionMode,
+        /// The id the server assigned to the client.
+        id: i64,
+        /// The ephemeral public key of the server.
+        public_key: [u8; 32],
+        /// The long-term public identity key of the server.
+        identity_key: [u8; 32],
+        /// The lobby the player was placed in.
+        lobby: LobbyInfo,
+    },
+    /// The public lobbies of the server, answering a browsing client before the connection is closed.
+    LobbyList { lobbies: Vec<LobbySummary> },
+    /// The server closes the connection.
+    Reject { reason: RejectReason },
+}
+
+/// The answer of the client to an accepting ServerHello.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ClientKeyExchange {
+    /// The ephemeral public key of the client.
+    pub public_key: [u8; 32],
+}
+
+impl ClientKeyExchange {
+    pub fn new(public_key: [u8; 32]) -> Self {
+        ClientKeyExchange { public_key }
+    }
+}
+
+/// The last message of the handshake, proving the identity of the server.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HandshakeFinished {
+    /// The signature of the server over the handshake transcript.
+    pub signature: Vec<u8>,
+}
+
+impl HandshakeFinished {
+    pub fn new(signature: Vec<u8>) -> Self {
+        HandshakeFinished { signature }
+    }
+}
+
+/// Why a server refused a connection during the handshake.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum RejectReason {
+    /// The client speaks a protocol version the server does not support.
+    UnsupportedVersion { supported: u8 },
+    /// None of the encryption modes offered by the client is accepted by the server.
+    NoCommonEncryptionMode { supported: Vec<EncryptionMode> },
+    /// The username is empty or only consists of whitespace.
+    EmptyUsername,
+    /// The username is longer than the server allows.
+    UsernameTooLong { max_length: usize },
+    /// The username contains control characters.
+    InvalidUsername,
+    /// The name of a new lobby is too long or contains control characters.
+    InvalidLobbyName { max_length: usize },
+    /// There is no public lobby with the requested id.
+    UnknownLobby,
+    /// There is no lobby with the given join code.
+    UnknownJoinCode,
+    /// The requested lobby has no free places.
+    LobbyFull,
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::UnsupportedVersion { supported } => write!(
+                f,
+                "the server speaks protocol version {}, please update your client",
+                supported
+            ),
+            RejectReason::NoCommonEncryptionMode { supported } => write!(
+                f,
+                "the server only accepts {} connections",
+                supported
+                    .iter()
+                    .map(|mode| mode.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" or ")
+            ),
+            RejectReason::EmptyUsername => write!(f, "please enter a username"),
+            RejectReason::UsernameTooLong { max_length } => write!(
+                f,
+                "the username may be at most {} characters long",
+                max_length
+            ),
+            RejectReason::InvalidUsername => {
+                write!(f, "the username may not contain control characters")
+            }
+            RejectReason::InvalidLobbyName { max_length } => write!(
+                f,
+                "the lobby name may be at most {} characters long and may not contain control characters",
+                max_length
+            ),
+            RejectReason::UnknownLobby => write!(f, "the lobby does not exist anymore"),
+            RejectReason::UnknownJoinCode => write!(f, "there is no lobby with this join code"),
+            RejectReason::LobbyFull => write!(f, "the lobby is full"),
+        }
+    }
+}
+
+/// Checks if a username may be used on a server.
+///
+/// # Arguments
+/// * `username` - The username sent by the client, surrounding whitespace is ignored.
+///
+/// # Returns
+/// * `Ok(())` - The username is valid.
+/// * `Err(reason)` - Why the username is refused.
+///
+pub fn validate_username(username: &str) -> Result<(), RejectReason> {
+    let username = username.trim();
+    if username.is_empty() {
+        Err(RejectReason::EmptyUsername)
+    } else if username.chars().count() > MAX_USERNAME_LENGTH {
+        Err(RejectReason::UsernameTooLong {
+            max_length: MAX_USERNAME_LENGTH,
+        })
+    } else if username.chars().any(char::is_control) {
+        Err(RejectReason::InvalidUsername)
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks if a name may be used for a new lobby.
+///
+/// # Arguments
+/// * `name` - The requested name, surrounding whitespace is ignored.
+///
+/// # Returns
+/// * `Ok(())` - The name is valid.
+/// * `Err(reason)` - Why the name is refused.
+///
+pub fn validate_lobby_name(name: &str) -> Result<(), RejectReason> {
+    let name = name.trim();
+    if name.chars().count() > MAX_LOBBY_NAME_LENGTH || name.chars().any(char::is_control) {
+        Err(RejectReason::InvalidLobbyName {
+            max_length: MAX_LOBBY_NAME_LENGTH,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Decides if the server can serve a client based on its hello.
+///
+/// # Arguments
+/// * `hello` - The hello sent by the client.
+/// * `accepted_modes` - The encryption modes the server accepts.
+///
+/// # Returns
+/// * `Ok(mode)` - The first mode preferred by the client that the server accepts.
+/// * `Err(reason)` - The versions differ, there is no common encryption mode or a name is invalid.
+///
+pub fn negotiate(
+    hello: &ClientHello,
+    accepted_modes: &[EncryptionMode],
+) -> Result<EncryptionMode, RejectReason> {
+    if hello.protocol_version != PROTOCOL_VERSION {
+        return Err(RejectReason::UnsupportedVersion {
+            supported: PROTOCOL_VERSION,
+        });
+    }
+    validate_username(&hello.username)?;
+    if let LobbyRequest::CreatePrivate { name } = &hello.lobby {
+        validate_lobby_name(name)?;
+    }
+    hello
+        .encryption_modes
+        .iter()
+        .find(|mode| accepted_modes.contains(mode))
+        .copied()
+        .ok_or_else(|| RejectReason::NoCommonEncryptionMode {
+            supported: accepted_modes.to_vec(),
+        })
+}
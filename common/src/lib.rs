@@ -1,3 +1,5 @@
 pub mod gamestate_common;
+pub mod handshake_common;
 pub mod messages_common;
 pub mod network_common;
+pub mod settings_common;
@@ -1,119 +1,300 @@
 use crate::gamestate_common::{GameState, Line, Player};
+use crate::settings_common::LobbySettings;
 use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
 
-#[derive(Serialize, Deserialize)]
+/// Longest chat message in characters a server passes on.
+pub const MAX_CHAT_MESSAGE_LENGTH: usize = 200;
+
+/// Every message exchanged between client and server.
+///
+/// Messages are serialized with a `kind` tag, so the wire format stays `{"kind": "...", ...}`.
+/// Any frame that does not decode into one of these variants is rejected with a
+/// `serde_json::Error` instead of being passed on.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Message {
+    /// client -> lobby: a freshly connected client announces its username
+    UserInit(UserInit),
+    /// client -> server: toggle the ready state in the lobby
+    Ready(ReadyMessage),
+    /// client <-> server: a chat message or guess
+    ChatMessage(ChatMessage),
+    /// client -> server: the client is leaving
+    Disconnect(DisconnectMessage),
+    /// server -> client: the current game state
+    #[serde(rename = "update")]
+    GameStateUpdate(GameStateUpdate),
+    /// server -> client: the current list of players
+    #[serde(rename = "player_update")]
+    PlayersUpdate(PlayersUpdate),
+    /// client <-> server: the drawer finished a line
+    AddLine(PaintingUpdate),
+    /// client <-> server: the drawer cleared the canvas
+    ClearAllLines(ClearLinesMessage),
+    /// client <-> server: the drawer removed the last line
+    ClearLastLine(ClearLinesMessage),
+    /// client -> server: the host changes the settings of the lobby
+    /// server -> client: the current settings of the lobby
+    #[serde(rename = "settings_update")]
+    LobbySettingsUpdate(LobbySettingsUpdate),
+    /// client -> server: the drawer picks one of the offered words
+    ChooseWord(ChooseWordMessage),
+    /// client -> server: the host replaces the custom words of the lobby
+    CustomWords(CustomWordsMessage),
+    /// server -> client: something happened in the lobby
+    Event(EventMessage),
+    /// server -> client: keep-alive check
+    Ping,
+    /// lobby internal: the round timer ran out
+    TimeUp,
+    /// lobby internal: broadcast a fresh game state and player list
+    UpdateRequested,
+}
+
+impl Message {
+    /// The id of the player a client message claims to be sent by.
+    ///
+    /// # Returns
+    /// * `Some(id)` - The player id carried by a message clients are allowed to send.
+    /// * `None` - The message kind may only be created by the server.
+    pub fn client_sender(&self) -> Option<i64> {
+        match self {
+            Message::Ready(ready) => Some(ready.id),
+            Message::ChatMessage(chat) => Some(chat.id),
+            Message::Disconnect(disconnect) => Some(disconnect.id),
+            Message::AddLine(painting_update) => Some(painting_update.id),
+            Message::ClearAllLines(clear) | Message::ClearLastLine(clear) => Some(clear.id),
+            Message::LobbySettingsUpdate(update) => Some(update.id),
+            Message::ChooseWord(choice) => Some(choice.id),
+            Message::CustomWords(custom_words) => Some(custom_words.id),
+            Message::UserInit(_)
+            | Message::Event(_)
+            | Message::GameStateUpdate(_)
+            | Message::PlayersUpdate(_)
+            | Message::Ping
+            | Message::TimeUp
+            | Message::UpdateRequested => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UserInit {
+    pub id: i64,
+    pub username: String,
+}
+
+impl UserInit {
+    pub fn new(id: i64, username: String) -> Self {
+        UserInit { id, username }
+    }
+}
+
+/// Who can read a chat message.
+#[derive(Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatChannel {
+    /// Everybody in the lobby.
+    #[default]
+    Everyone,
+    /// Only the drawer and the players who already guessed the word.
+    Guessed,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct ChatMessage {
-    pub kind: String,
     pub id: i64,
     pub message: String,
+    /// chosen by the server, the channel a client asks for is ignored
+    #[serde(default)]
+    pub channel: ChatChannel,
 }
 
 impl ChatMessage {
     pub fn new(id: i64, message: String) -> Self {
+        ChatMessage::in_channel(id, message, ChatChannel::Everyone)
+    }
+
+    pub fn in_channel(id: i64, message: String, channel: ChatChannel) -> Self {
         ChatMessage {
-            kind: "chat_message".to_string(),
             id,
             message,
+            channel,
         }
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct ReadyMessage {
-    pub kind: String,
     pub id: i64,
     pub ready: bool,
 }
 
 impl ReadyMessage {
     pub fn new(id: i64, ready: bool) -> Self {
-        ReadyMessage {
-            kind: "ready".to_string(),
-            id,
-            ready,
-        }
+        ReadyMessage { id, ready }
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct DisconnectMessage {
-    pub kind: String,
     pub id: i64,
 }
 
 impl DisconnectMessage {
     pub fn new(id: i64) -> Self {
-        DisconnectMessage {
-            kind: "disconnect".to_string(),
-            id,
-        }
+        DisconnectMessage { id }
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct GameStateUpdate {
-    pub kind: String,
-    pub id: i64,
-    pub game_state: Value,
+    pub game_state: GameState,
 }
 
 impl GameStateUpdate {
     pub fn new(game_state: GameState) -> Self {
-        GameStateUpdate {
-            kind: "update".to_string(),
-            id: 0,
-            game_state: json!(game_state),
-        }
+        GameStateUpdate { game_state }
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct PlayersUpdate {
-    pub kind: String,
-    pub id: i64,
-    pub players: Value,
+    pub players: Vec<Player>,
 }
 
 impl PlayersUpdate {
     pub fn new(players: Vec<Player>) -> Self {
-        PlayersUpdate {
-            kind: "player_update".to_string(),
-            id: 0,
-            players: json!(players),
-        }
+        PlayersUpdate { players }
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct PaintingUpdate {
-    pub kind: String,
     pub id: i64,
-    pub line: Value,
+    pub line: Line,
 }
 
 impl PaintingUpdate {
     pub fn new(id: i64, line: Line) -> Self {
-        PaintingUpdate {
-            kind: "add_line".to_string(),
-            id,
-            line: json!(line),
-        }
+        PaintingUpdate { id, line }
     }
+}
 
-    pub fn clear_all(id: i64) -> Self {
-        PaintingUpdate {
-            kind: "clear_all_lines".to_string(),
-            id,
-            line: Value::Null,
-        }
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ClearLinesMessage {
+    pub id: i64,
+}
+
+impl ClearLinesMessage {
+    pub fn new(id: i64) -> Self {
+        ClearLinesMessage { id }
     }
+}
 
-    pub fn clear_last(id: i64) -> Self {
-        PaintingUpdate {
-            kind: "clear_last_line".to_string(),
-            id,
-            line: Value::Null,
-        }
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LobbySettingsUpdate {
+    /// the player changing the settings, the host for updates sent by the server
+    pub id: i64,
+    pub settings: LobbySettings,
+}
+
+impl LobbySettingsUpdate {
+    pub fn new(id: i64, settings: LobbySettings) -> Self {
+        LobbySettingsUpdate { id, settings }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChooseWordMessage {
+    pub id: i64,
+    /// the index of the chosen word in `word_choices` of the game state
+    pub choice: usize,
+}
+
+impl ChooseWordMessage {
+    pub fn new(id: i64, choice: usize) -> Self {
+        ChooseWordMessage { id, choice }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CustomWordsMessage {
+    pub id: i64,
+    /// the words as typed by the host, an empty list removes the custom words
+    pub words: Vec<String>,
+}
+
+impl CustomWordsMessage {
+    pub fn new(id: i64, words: Vec<String>) -> Self {
+        CustomWordsMessage { id, words }
+    }
+}
+
+/// Who receives an event.
+#[derive(Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Audience {
+    /// Everybody in the lobby.
+    Everyone,
+    /// Only the player with this id.
+    Player(i64),
+}
+
+/// Something that happened in a lobby, shown in the chat apart from the messages of the players.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum GameEvent {
+    PlayerJoined {
+        name: String,
+    },
+    PlayerLeft {
+        name: String,
+    },
+    /// A player guessed the word.
+    CorrectGuess {
+        id: i64,
+        name: String,
+    },
+    /// The guess of the receiving player was almost the word.
+    CloseGuess {
+        guess: String,
+    },
+    RoundStarted {
+        round: u32,
+        rounds: u32,
+    },
+    RoundEnded {
+        round: u32,
+    },
+    /// The word of the turn that just ended.
+    WordRevealed {
+        word: String,
+    },
+    /// A notice of the server, like why a message or the settings were refused.
+    Notice {
+        text: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct EventMessage {
+    pub audience: Audience,
+    pub event: GameEvent,
+}
+
+impl EventMessage {
+    pub fn new(audience: Audience, event: GameEvent) -> Self {
+        EventMessage { audience, event }
+    }
+
+    /// An event every player in the lobby receives.
+    pub fn broadcast(event: GameEvent) -> Self {
+        EventMessage::new(Audience::Everyone, event)
+    }
+
+    /// An event only one player receives.
+    pub fn private(id: i64, event: GameEvent) -> Self {
+        EventMessage::new(Audience::Player(id), event)
     }
 }
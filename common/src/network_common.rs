@@ -1,8 +1,13 @@
-use chacha20poly1305::{Key, Nonce};
-use rand::Rng;
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use ed25519_dalek::Verifier;
+use hkdf::Hkdf;
 use rand_core::OsRng;
-use serde_json::Value;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 use std::error;
+use std::fmt;
 use std::io::Error;
 use std::io::ErrorKind;
 use std::io::Read;
@@ -10,22 +15,384 @@ use std::io::Write;
 use std::net::TcpStream;
 use x25519_dalek::{PublicKey, ReusableSecret};
 
-#[cfg(not(feature = "no-encryption"))]
-use {
-    chacha20poly1305::aead::{Aead, NewAead},
-    chacha20poly1305::ChaCha20Poly1305,
-    generic_array::GenericArray,
-};
+use crate::handshake_common::EncryptionMode;
+use crate::messages_common::Message;
+
+/// Domain separation label for the handshake transcript signed by the server.
+const HANDSHAKE_LABEL: &[u8] = b"rust-scribble-handshake-v1";
+/// Labels for deriving the key of each direction from the shared secret.
+const CLIENT_TO_SERVER_LABEL: &[u8] = b"rust-scribble client to server";
+const SERVER_TO_CLIENT_LABEL: &[u8] = b"rust-scribble server to client";
+/// Size of the nonce counter in front of every encrypted message.
+const COUNTER_SIZE: usize = 8;
+/// Size of the checksum at the end of every plaintext message.
+const CHECKSUM_SIZE: usize = 4;
+/// Size of the authentication tag the encryption adds to every message.
+const TAG_SIZE: usize = 16;
+
+/// Magic value every frame starts with.
+pub const FRAME_MAGIC: [u8; 4] = *b"SCRB";
+/// Version of the wire protocol, frames of any other version are rejected.
+pub const PROTOCOL_VERSION: u8 = 15;
+/// Size of the frame header: magic value, protocol version and body length.
+pub const FRAME_HEADER_SIZE: usize = 9;
+/// Default maximum size of a frame body in bytes.
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 1024 * 1024;
+/// Number of bytes requested from the stream per read.
+const READ_CHUNK_SIZE: usize = 4096;
 
 pub struct NetworkInfo {
     /// The name of the client.
     pub id: i64,
     /// The tcp_stream of the client.
     pub tcp_stream: TcpStream,
-    /// The public key of the client.
-    pub key: Key,
-    /// The shared secret of the client and server.
-    pub secret_key: Option<ReusableSecret>,
+    /// The session between client and server.
+    pub session: Session,
+    /// The bytes received so far that do not form a complete frame yet.
+    pub decoder: FrameDecoder,
+}
+
+/// Errors of the wire protocol between client and server.
+#[derive(Debug)]
+pub enum ProtocolError {
+    /// The frame does not carry the nonce counter of the next expected message,
+    /// it is either replayed or out of order.
+    UnexpectedCounter { expected: u64, received: u64 },
+    /// All nonce counters of the session are used up, the connection has to be closed.
+    SessionExhausted,
+    /// The frame could not be authenticated with the key of the session.
+    DecryptionFailed,
+    /// The frame is too short to hold a counter and a checksum.
+    TruncatedFrame,
+    /// The frame does not start with the magic value, the stream is out of sync or not ours.
+    BadMagic,
+    /// The other side speaks a different version of the protocol.
+    VersionMismatch { ours: u8, theirs: u8 },
+    /// The frame body is larger than the maximum frame size.
+    FrameTooLarge { size: u64, max: u32 },
+}
+
+impl ProtocolError {
+    /// Checks if the connection has to be closed, because the stream is in an unknown state
+    /// or somebody is forging or replaying frames of the session.
+    /// After any other error only the affected frame is dropped.
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            ProtocolError::UnexpectedCounter { .. }
+                | ProtocolError::DecryptionFailed
+                | ProtocolError::SessionExhausted
+                | ProtocolError::BadMagic
+                | ProtocolError::VersionMismatch { .. }
+                | ProtocolError::FrameTooLarge { .. }
+        )
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::UnexpectedCounter { expected, received } => write!(
+                f,
+                "Rejected replayed or out of order message {} (expected {})",
+                received, expected
+            ),
+            ProtocolError::SessionExhausted => write!(f, "Session has run out of nonces"),
+            ProtocolError::DecryptionFailed => write!(f, "Decryption failed!"),
+            ProtocolError::TruncatedFrame => write!(f, "Message is too short"),
+            ProtocolError::BadMagic => write!(f, "Received data that is not a rust scribble frame"),
+            ProtocolError::VersionMismatch { ours, theirs } => write!(
+                f,
+                "Protocol version mismatch: we speak version {} but the other side speaks version {}",
+                ours, theirs
+            ),
+            ProtocolError::FrameTooLarge { size, max } => write!(
+                f,
+                "Frame of {} bytes exceeds the maximum frame size of {} bytes",
+                size, max
+            ),
+        }
+    }
+}
+
+impl error::Error for ProtocolError {}
+
+/// The fixed-width header in front of every frame.
+///
+/// Layout: 4 byte magic value, 1 byte protocol version, 4 byte big-endian body length.
+pub struct FrameHeader {
+    /// The protocol version of the sender.
+    pub version: u8,
+    /// The length of the frame body following the header.
+    pub length: u32,
+}
+
+impl FrameHeader {
+    /// Creates the header for a body of the given length in the current protocol version.
+    ///
+    /// # Arguments
+    /// * `length` - The length of the frame body.
+    ///
+    /// # Returns
+    /// * `Ok(header)` - The header for the body.
+    /// * `Err(ProtocolError::FrameTooLarge)` - The body does not fit into a frame.
+    ///
+    pub fn new(length: usize) -> Result<Self, ProtocolError> {
+        let length = u32::try_from(length).map_err(|_| ProtocolError::FrameTooLarge {
+            size: length as u64,
+            max: u32::MAX,
+        })?;
+        Ok(FrameHeader {
+            version: PROTOCOL_VERSION,
+            length,
+        })
+    }
+
+    /// Encodes the header for sending.
+    pub fn to_bytes(&self) -> [u8; FRAME_HEADER_SIZE] {
+        let mut bytes = [0; FRAME_HEADER_SIZE];
+        bytes[..4].copy_from_slice(&FRAME_MAGIC);
+        bytes[4] = self.version;
+        bytes[5..].copy_from_slice(&self.length.to_be_bytes());
+        bytes
+    }
+
+    /// Decodes and validates a received header.
+    ///
+    /// # Arguments
+    /// * `bytes` - The received header bytes.
+    /// * `max_frame_size` - The largest body length that will be accepted.
+    ///
+    /// # Returns
+    /// * `Ok(header)` - A header of our protocol version within the size limit.
+    /// * `Err(e)` - The ProtocolError describing why the header was rejected.
+    ///
+    pub fn parse(
+        bytes: &[u8; FRAME_HEADER_SIZE],
+        max_frame_size: u32,
+    ) -> Result<Self, ProtocolError> {
+        let header = FrameHeader::parse_any_version(bytes, max_frame_size)?;
+        if header.version != PROTOCOL_VERSION {
+            return Err(ProtocolError::VersionMismatch {
+                ours: PROTOCOL_VERSION,
+                theirs: header.version,
+            });
+        }
+        Ok(header)
+    }
+
+    /// Decodes a received header without checking the protocol version.
+    ///
+    /// Only used for the handshake, so a peer speaking another version can still be told why
+    /// the connection is refused.
+    ///
+    /// # Arguments
+    /// * `bytes` - The received header bytes.
+    /// * `max_frame_size` - The largest body length that will be accepted.
+    ///
+    /// # Returns
+    /// * `Ok(header)` - A header within the size limit.
+    /// * `Err(e)` - The ProtocolError describing why the header was rejected.
+    ///
+    pub fn parse_any_version(
+        bytes: &[u8; FRAME_HEADER_SIZE],
+        max_frame_size: u32,
+    ) -> Result<Self, ProtocolError> {
+        if bytes[..4] != FRAME_MAGIC {
+            return Err(ProtocolError::BadMagic);
+        }
+        let version = bytes[4];
+        let length = u32::from_be_bytes([bytes[5], bytes[6], bytes[7], bytes[8]]);
+        if length > max_frame_size {
+            return Err(ProtocolError::FrameTooLarge {
+                size: length as u64,
+                max: max_frame_size,
+            });
+        }
+        Ok(FrameHeader { version, length })
+    }
+}
+
+/// A function decoding and validating a frame header, see FrameHeader::parse().
+pub type HeaderParser = fn(&[u8; FRAME_HEADER_SIZE], u32) -> Result<FrameHeader, ProtocolError>;
+
+/// Collects the bytes received on a stream until they form complete frames.
+///
+/// A read may end in the middle of a frame, for example on a read timeout.
+/// The bytes received so far are kept for the next read, so the stream stays in sync.
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    /// Frames announcing a larger body are rejected before the body is received.
+    max_frame_size: u32,
+}
+
+impl FrameDecoder {
+    pub fn new(max_frame_size: u32) -> Self {
+        FrameDecoder {
+            buffer: Vec::new(),
+            max_frame_size,
+        }
+    }
+
+    /// Checks if a complete frame has been received but not taken yet.
+    pub fn has_frame(&self) -> bool {
+        self.buffer.len() >= FRAME_HEADER_SIZE
+            && self.buffer.len() - FRAME_HEADER_SIZE
+                >= u32::from_be_bytes([
+                    self.buffer[5],
+                    self.buffer[6],
+                    self.buffer[7],
+                    self.buffer[8],
+                ]) as usize
+    }
+
+    /// Takes the next complete frame out of the received bytes.
+    ///
+    /// # Arguments
+    /// * `parse_header` - Decodes and validates the header of the frame.
+    ///
+    /// # Returns
+    /// * `Ok(Some(body))` - The body of the next frame.
+    /// * `Ok(None)` - The next frame has not been received completely yet.
+    /// * `Err(e)` - The header was rejected, the connection has to be closed.
+    ///
+    pub fn next_frame(
+        &mut self,
+        parse_header: HeaderParser,
+    ) -> Result<Option<Vec<u8>>, ProtocolError> {
+        if self.buffer.len() < FRAME_HEADER_SIZE {
+            return Ok(None);
+        }
+        let mut header = [0; FRAME_HEADER_SIZE];
+        header.copy_from_slice(&self.buffer[..FRAME_HEADER_SIZE]);
+        let header = parse_header(&header, self.max_frame_size)?;
+
+        let frame_size = FRAME_HEADER_SIZE + header.length as usize;
+        if self.buffer.len() < frame_size {
+            return Ok(None);
+        }
+        let body = self.buffer[FRAME_HEADER_SIZE..frame_size].to_vec();
+        self.buffer.drain(..frame_size);
+        Ok(Some(body))
+    }
+
+    /// Reads from the stream until a complete frame has been received.
+    ///
+    /// # Arguments
+    /// * `reader` - The stream to read from.
+    /// * `parse_header` - Decodes and validates the header of the frame.
+    ///
+    /// # Returns
+    /// * `Ok(body)` - The body of the next frame.
+    /// * `Err(e)` - The read failed or timed out, a partially received frame is kept.
+    ///   A `ProtocolError` means the header was rejected and the connection has to be closed.
+    ///
+    pub fn read_frame<R: Read>(
+        &mut self,
+        reader: &mut R,
+        parse_header: HeaderParser,
+    ) -> Result<Vec<u8>, Box<dyn error::Error>> {
+        let mut chunk = [0; READ_CHUNK_SIZE];
+        loop {
+            if let Some(body) = self.next_frame(parse_header)? {
+                return Ok(body);
+            }
+            match reader.read(&mut chunk) {
+                Ok(0) => {
+                    return Err(Box::new(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "Connection closed by the other side",
+                    )))
+                }
+                Ok(read) => self.buffer.extend_from_slice(&chunk[..read]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(Box::new(e)),
+            }
+        }
+    }
+}
+
+/// The end of the connection a session belongs to.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum Side {
+    Client,
+    Server,
+}
+
+/// The key and nonce counter of one direction of a connection.
+struct CipherState {
+    key: Key,
+    /// counter of the next message, it is used as the nonce of the message
+    counter: u64,
+}
+
+/// The encryption state of an established connection.
+///
+/// Each direction has its own key derived from the shared secret and a counter
+/// that is increased with every message, so every frame can be accepted exactly once and in order.
+pub struct Session {
+    send: CipherState,
+    receive: CipherState,
+    /// The encryption mode negotiated in the handshake.
+    mode: EncryptionMode,
+}
+
+impl Session {
+    /// Derives the keys of both directions from the result of the key exchange.
+    ///
+    /// # Arguments
+    /// * `shared_secret` - The Diffie-Hellman shared secret of client and server.
+    /// * `transcript` - The handshake transcript, binding the keys to this handshake.
+    /// * `side` - The end of the connection the session is used on.
+    /// * `mode` - The encryption mode negotiated in the handshake.
+    ///
+    pub fn new(shared_secret: &[u8], transcript: &[u8], side: Side, mode: EncryptionMode) -> Self {
+        let hkdf = Hkdf::<Sha256>::new(Some(transcript), shared_secret);
+        let derive_key = |label: &[u8]| {
+            let mut key = [0; 32];
+            hkdf.expand(label, &mut key)
+                .expect("32 bytes is a valid output length for HKDF-SHA256");
+            Key::from(key)
+        };
+        let client_to_server = CipherState {
+            key: derive_key(CLIENT_TO_SERVER_LABEL),
+            counter: 0,
+        };
+        let server_to_client = CipherState {
+            key: derive_key(SERVER_TO_CLIENT_LABEL),
+            counter: 0,
+        };
+        match side {
+            Side::Client => Session {
+                send: client_to_server,
+                receive: server_to_client,
+                mode,
+            },
+            Side::Server => Session {
+                send: server_to_client,
+                receive: client_to_server,
+                mode,
+            },
+        }
+    }
+
+    /// The encryption mode of the session.
+    pub fn mode(&self) -> EncryptionMode {
+        self.mode
+    }
+
+    /// Checks if one of the counters has reached its maximum.
+    /// The connection has to be closed before the counter would wrap and nonces would repeat.
+    pub fn is_exhausted(&self) -> bool {
+        self.send.counter == u64::MAX || self.receive.counter == u64::MAX
+    }
+}
+
+/// Creates the nonce for the message with the given counter.
+fn nonce_for(counter: u64) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    Nonce::from(nonce)
 }
 
 /// Verifies if the checksum of the cipher text is correct.
@@ -57,97 +424,192 @@ pub fn generate_keypair() -> (PublicKey, ReusableSecret) {
     (public, secret)
 }
 
+/// Builds the handshake transcript the server signs with its long-term identity key.
+///
+/// The transcript binds both ephemeral keys, the client id, the negotiated encryption mode
+/// and both hellos to the identity of the server, so a man-in-the-middle cannot swap in its own
+/// ephemeral key, downgrade the connection or change the lobby the client asked for or was told it joined.
+///
+/// # Arguments
+/// * `client_hello` - The body of the ClientHello frame, exactly as sent.
+/// * `server_hello` - The body of the ServerHello frame, exactly as sent.
+/// * `server_public` - The ephemeral public key of the server.
+/// * `id` - The id the server assigned to the client.
+/// * `identity_key` - The long-term public identity key of the server.
+/// * `client_public` - The ephemeral public key of the client.
+/// * `mode` - The encryption mode negotiated in the handshake.
+///
+/// # Returns
+/// * `transcript` - The bytes to be signed and verified.
+///
+pub fn handshake_transcript(
+    client_hello: &[u8],
+    server_hello: &[u8],
+    server_public: &PublicKey,
+    id: i64,
+    identity_key: &[u8; 32],
+    client_public: &PublicKey,
+    mode: EncryptionMode,
+) -> Vec<u8> {
+    let mut transcript = HANDSHAKE_LABEL.to_vec();
+    transcript.push(PROTOCOL_VERSION);
+    transcript.push(mode as u8);
+    transcript.extend_from_slice(server_public.as_bytes());
+    transcript.extend_from_slice(&id.to_be_bytes());
+    transcript.extend_from_slice(identity_key);
+    transcript.extend_from_slice(client_public.as_bytes());
+    transcript.extend_from_slice(&Sha256::digest(client_hello));
+    transcript.extend_from_slice(&Sha256::digest(server_hello));
+    transcript
+}
+
+/// Verifies the signature of the server over the handshake transcript.
+///
+/// # Arguments
+/// * `identity_key` - The long-term public identity key of the server.
+/// * `transcript` - The transcript created by handshake_transcript().
+/// * `signature` - The signature sent by the server.
+///
+/// # Returns
+/// * `Ok(())` - The transcript was signed by the owner of the identity key.
+/// * `Err(e)` - The key or the signature is invalid.
+///
+pub fn verify_handshake_signature(
+    identity_key: &[u8; 32],
+    transcript: &[u8],
+    signature: &[u8; 64],
+) -> Result<(), Error> {
+    let invalid = |_| Error::new(ErrorKind::InvalidData, "Invalid server signature");
+    let identity = ed25519_dalek::PublicKey::from_bytes(identity_key).map_err(invalid)?;
+    let signature = ed25519_dalek::Signature::from_bytes(signature).map_err(invalid)?;
+    identity.verify(transcript, &signature).map_err(invalid)
+}
+
+/// Creates a human readable fingerprint of a server identity key.
+///
+/// # Arguments
+/// * `identity_key` - The long-term public identity key of the server.
+///
+/// # Returns
+/// * `fingerprint` - The hex encoded SHA-256 hash of the key.
+///
+pub fn fingerprint(identity_key: &[u8]) -> String {
+    Sha256::digest(identity_key)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
 /// Encrypts a JSON message
 ///
 /// # Arguments
 /// * `json_message` - The message to be encrypted.
-/// * `share_key` - The shared key to be used for encryption.
+/// * `session` - The session whose sending key and counter are used.
 ///
 /// # Returns
-/// * packed_message Vec<u8> - The packed network encrypted message.
+/// * `Ok(packed_message)` - The packed network encrypted message.
+/// * `Err(ProtocolError::SessionExhausted)` - The session has to be closed.
+/// * `Err(ProtocolError::FrameTooLarge)` - The message does not fit into a frame, the session is unchanged.
 ///
-pub fn encrypt_json(mut json_message: Vec<u8>, _shared_key: Key) -> Vec<u8> {
-    let nonce = *Nonce::from_slice(rand::thread_rng().gen::<[u8; 12]>().as_slice());
+pub fn encrypt_json(
+    mut json_message: Vec<u8>,
+    session: &mut Session,
+) -> Result<Vec<u8>, ProtocolError> {
+    if session.is_exhausted() {
+        return Err(ProtocolError::SessionExhausted);
+    }
+    let tag_size = match session.mode {
+        EncryptionMode::Encrypted => TAG_SIZE,
+        EncryptionMode::Plaintext => 0,
+    };
+    // a message too large to send must not use up a counter
+    let header = FrameHeader::new(json_message.len() + CHECKSUM_SIZE + tag_size + COUNTER_SIZE)?;
+    let counter = session.send.counter;
+    session.send.counter += 1;
+
     let checksum = crc32fast::hash(&json_message[..]);
 
     for byte in checksum.to_be_bytes().iter() {
         json_message.push(*byte);
     }
 
-    #[cfg(not(feature = "no-encryption"))]
-    let ciphertext = ChaCha20Poly1305::new(&_shared_key)
-        .encrypt(&nonce, &json_message[..])
-        .expect("encryption failure!");
+    let ciphertext = match session.mode {
+        EncryptionMode::Encrypted => ChaCha20Poly1305::new(&session.send.key)
+            .encrypt(&nonce_for(counter), &json_message[..])
+            .expect("encryption failure!"),
+        EncryptionMode::Plaintext => json_message,
+    };
 
-    #[cfg(feature = "no-encryption")]
-    let ciphertext = json_message.clone();
-
-    //Add 12 bytes for the nonce and 4 bytes for the checksum
-    let msg_size = ciphertext.len() + 12;
-    pack_network_message(msg_size, nonce, ciphertext)
+    debug_assert_eq!(header.length as usize, ciphertext.len() + COUNTER_SIZE);
+    Ok(pack_network_message(header, counter, ciphertext))
 }
 
 /// Decrypts a JSON message
 ///
+/// Only the message with the next expected counter is accepted,
+/// replayed or reordered messages are rejected before decryption.
+///
 /// # Arguments
 /// * `msg_buf` - The message buffer to be decrypted.
-/// * `msg_size` - The size of the message.
-/// * `key` - The shared key to be used for encryption.
+/// * `session` - The session whose receiving key and counter are used.
 ///
 /// # Returns
-/// * Result<Value, Error> - The json decrypted message if ok
+/// * Result<Message, Error> - The decrypted message if ok.
+///   A message that is not valid JSON or has an unknown `kind` yields a `serde_json::Error`,
+///   a message that violates the protocol yields a `ProtocolError`.
 ///
 pub fn decrypt_message(
-    msg_buf: &mut Vec<u8>,
-    msg_size: usize,
-    _key: &Key,
-) -> Result<serde_json::Value, Box<dyn error::Error>> {
-    let json_message: Value;
-    #[cfg(not(feature = "no-encryption"))]
-    {
-        let cipher = ChaCha20Poly1305::new(&_key);
-
-        let nonce: Nonce = GenericArray::clone_from_slice(&msg_buf[0..12]);
-        let ciphertext = &msg_buf[12..msg_size];
-
-        json_message = match cipher.decrypt(&nonce, ciphertext) {
-            Ok(plaintext) => {
-                //if check_checksum of ciphertext returns false, throw error
-                //get the last four bytes of the plaintext and put it a checksum variable
-                let vec_text = &plaintext.to_vec()[..plaintext.len() - 4];
-                let checksum: u32 =
-                    u32::from_be_bytes(plaintext[plaintext.len() - 4..plaintext.len()].try_into()?);
-                check_checksum(vec_text, checksum)?;
-                serde_json::from_slice(&vec_text)?
-            }
-            Err(_) => return Err(Box::new(Error::new(ErrorKind::Other, "Decryption failed!"))),
-        };
+    msg_buf: &[u8],
+    session: &mut Session,
+) -> Result<Message, Box<dyn error::Error>> {
+    if session.is_exhausted() {
+        return Err(Box::new(ProtocolError::SessionExhausted));
+    }
+    if msg_buf.len() < COUNTER_SIZE + CHECKSUM_SIZE {
+        return Err(Box::new(ProtocolError::TruncatedFrame));
     }
 
-    #[cfg(feature = "no-encryption")]
-    {
-        let ciphertext = &msg_buf[12..msg_size - 4];
-        let result: Result<Value, serde_json::Error> = serde_json::from_slice(&ciphertext);
-        json_message = result?;
+    let counter = u64::from_be_bytes(msg_buf[..COUNTER_SIZE].try_into()?);
+    if counter != session.receive.counter {
+        return Err(Box::new(ProtocolError::UnexpectedCounter {
+            expected: session.receive.counter,
+            received: counter,
+        }));
     }
+    let ciphertext = &msg_buf[COUNTER_SIZE..];
+
+    let plaintext = match session.mode {
+        EncryptionMode::Encrypted => ChaCha20Poly1305::new(&session.receive.key)
+            .decrypt(&nonce_for(counter), ciphertext)
+            .map_err(|_| ProtocolError::DecryptionFailed)?,
+        EncryptionMode::Plaintext => ciphertext.to_vec(),
+    };
 
-    Ok(json_message)
+    // only authentic messages use up a counter, so forged frames cannot skip ahead
+    session.receive.counter += 1;
+
+    if plaintext.len() < CHECKSUM_SIZE {
+        return Err(Box::new(ProtocolError::TruncatedFrame));
+    }
+    //get the last four bytes of the plaintext and put it a checksum variable
+    let (json_text, checksum) = plaintext.split_at(plaintext.len() - CHECKSUM_SIZE);
+    check_checksum(json_text, u32::from_be_bytes(checksum.try_into()?))?;
+    Ok(serde_json::from_slice(json_text)?)
 }
 
 /// Packs a the components of a message into a singular message
 ///
 /// # Arguments
-/// * `msg_size` - The size of the message.
-/// * `nonce` - The nonce of the message.
+/// * `header` - The frame header announcing the size of the message.
+/// * `counter` - The nonce counter of the message.
 /// * `cipher_text` - The cipher text of the message.
-/// * `checksum` - The checksum of the message.
 ///
 /// # Returns
 /// * packed_message Vec<u8> - The packed network encrypted message.
-fn pack_network_message(msg_size: usize, nonce: Nonce, ciphertext: Vec<u8>) -> Vec<u8> {
+fn pack_network_message(header: FrameHeader, counter: u64, ciphertext: Vec<u8>) -> Vec<u8> {
     let mut message = vec![];
-    message.extend_from_slice(&msg_size.to_le_bytes());
-    message.extend_from_slice(&nonce);
+    message.extend_from_slice(&header.to_bytes());
+    message.extend_from_slice(&counter.to_be_bytes());
     message.extend_from_slice(&ciphertext);
     message
 }
@@ -158,15 +620,24 @@ fn pack_network_message(msg_size: usize, nonce: Nonce, ciphertext: Vec<u8>) -> V
 /// * `net_info` - The network information
 ///
 /// # Returns
-/// * `true` - If there are messages waiting to be read.
+/// * `true` - If there are messages waiting to be read, or the connection was closed and reading reports why.
 /// * `false` - If there are no messages waiting to be read.
 ///
 /// This function should be used in a thread to force updates as soon as a message is waiting to be read.
 ///
 pub fn message_waiting(net_info: &mut NetworkInfo) -> bool {
+    if net_info.decoder.has_frame() {
+        return true;
+    }
     let buf = &mut [0; 1];
-    let res = net_info.tcp_stream.peek(buf);
-    res.is_ok() && res.unwrap() > 0
+    match net_info.tcp_stream.peek(buf) {
+        // nothing to peek at means the other side closed the connection
+        Ok(_) => true,
+        Err(e) => !matches!(
+            e.kind(),
+            ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+        ),
+    }
 }
 
 /// Sends a message to a client.
@@ -183,44 +654,125 @@ pub fn send_tcp_message(tcp_stream: &mut TcpStream, net_msg: Vec<u8>) -> Result<
     Ok(tcp_stream.write_all(&net_msg)?)
 }
 
-/// Sends a JSON message to the server
+/// Sends a message to the other side of the connection
 ///
 /// # Arguments
-/// * `net_info` - The network information of the client.
-/// * `msg` - The message JSON Value to be sent.
+/// * `net_info` - The network information of the connection.
+/// * `msg` - The message to be sent.
 ///
 /// # Returns
 /// * `Ok(())` - The message was sent successfully.
 /// * `Err(e)` - The error that occurred.
 ///
-pub fn send_message(net_info: &mut NetworkInfo, msg: &Value) -> Result<(), Error> {
-    send_tcp_message(
-        &mut net_info.tcp_stream,
-        encrypt_json(msg.to_string().into_bytes(), net_info.key),
-    )
+pub fn send_message(net_info: &mut NetworkInfo, msg: &Message) -> Result<(), Error> {
+    let net_msg = encrypt_json(serde_json::to_vec(msg)?, &mut net_info.session)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    send_tcp_message(&mut net_info.tcp_stream, net_msg)
 }
 
-/// Reads a tcp_message from the client.
+/// Reads a tcp_message from the other side of the connection.
 ///
 /// # Arguments
-/// * `net_info` - The network information of the client.
+/// * `net_info` - The network information of the connection.
 ///
 /// # Returns
-/// * `Ok(msg)` - The message read from the client in JSON format.
-/// * `Err(e)` - The error that occurred.
+/// * `Ok(msg)` - The message read.
+/// * `Err(e)` - The error that occurred, a frame that was only partially received is kept
+///   until the next call. A `ProtocolError` for which is_fatal() is true means the connection has to be closed.
+///
+pub fn read_tcp_message(net_info: &mut NetworkInfo) -> Result<Message, Box<dyn error::Error>> {
+    let msg_buf = net_info
+        .decoder
+        .read_frame(&mut net_info.tcp_stream, FrameHeader::parse)?;
+    decrypt_message(&msg_buf, &mut net_info.session)
+}
+
+/// Packs an unencrypted handshake message into a frame of the current protocol version.
 ///
-pub fn read_tcp_message(
-    net_info: &mut NetworkInfo,
-) -> Result<serde_json::Value, Box<dyn error::Error>> {
-    let mut size = [0; (usize::BITS / 8) as usize];
-    let msg_size;
-    let mut msg_buf;
+/// # Arguments
+/// * `msg` - The handshake message to be packed.
+///
+/// # Returns
+/// * `Ok(frame)` - The frame ready to be sent.
+/// * `Err(e)` - The message could not be serialized or is too large.
+///
+pub fn encode_handshake_message<T: Serialize>(msg: &T) -> Result<Vec<u8>, Error> {
+    encode_handshake_frame(&serde_json::to_vec(msg)?)
+}
+
+/// Packs the serialized body of a handshake message into a frame of the current protocol version.
+///
+/// # Arguments
+/// * `body` - The serialized handshake message.
+///
+/// # Returns
+/// * `Ok(frame)` - The frame ready to be sent.
+/// * `Err(e)` - The body is too large.
+///
+pub fn encode_handshake_frame(body: &[u8]) -> Result<Vec<u8>, Error> {
+    let header = FrameHeader::new(body.len()).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    let mut frame = header.to_bytes().to_vec();
+    frame.extend_from_slice(body);
+    Ok(frame)
+}
 
-    net_info.tcp_stream.read_exact(&mut size)?;
-    msg_size = usize::from_le_bytes(size);
+/// Sends an unencrypted handshake message in a frame of the current protocol version.
+///
+/// # Arguments
+/// * `tcp_stream` - The tcp_stream of the connection.
+/// * `msg` - The handshake message to be sent.
+///
+/// # Returns
+/// * `Ok(())` - The message was sent successfully.
+/// * `Err(e)` - The error that occurred.
+///
+pub fn send_handshake_message<T: Serialize>(
+    tcp_stream: &mut TcpStream,
+    msg: &T,
+) -> Result<(), Error> {
+    send_tcp_message(tcp_stream, encode_handshake_message(msg)?)
+}
 
-    msg_buf = vec![0; msg_size];
-    net_info.tcp_stream.read_exact(&mut msg_buf)?;
+/// Reads an unencrypted handshake message.
+///
+/// The protocol version of the frame is not checked, the handshake messages carry
+/// the version themselves so a mismatch can be answered with a readable reason.
+///
+/// # Arguments
+/// * `tcp_stream` - The tcp_stream of the connection.
+/// * `decoder` - The decoder of the connection, it keeps bytes received after the message.
+///
+/// # Returns
+/// * `Ok(msg)` - The handshake message read.
+/// * `Err(e)` - The stream broke or did not contain the expected message.
+///
+pub fn read_handshake_message<T: DeserializeOwned>(
+    tcp_stream: &mut TcpStream,
+    decoder: &mut FrameDecoder,
+) -> Result<T, Error> {
+    let body = read_handshake_body(tcp_stream, decoder)?;
+    Ok(serde_json::from_slice(&body)?)
+}
 
-    decrypt_message(&mut msg_buf, msg_size, &net_info.key)
+/// Reads the body of an unencrypted handshake frame without parsing it,
+/// for messages that are part of the handshake transcript.
+///
+/// # Arguments
+/// * `tcp_stream` - The tcp_stream of the connection.
+/// * `decoder` - The decoder of the connection, it keeps bytes received after the message.
+///
+/// # Returns
+/// * `Ok(body)` - The body of the frame, exactly as sent.
+/// * `Err(e)` - The stream broke or did not contain a frame.
+///
+pub fn read_handshake_body(
+    tcp_stream: &mut TcpStream,
+    decoder: &mut FrameDecoder,
+) -> Result<Vec<u8>, Error> {
+    decoder
+        .read_frame(tcp_stream, FrameHeader::parse_any_version)
+        .map_err(|e| match e.downcast::<Error>() {
+            Ok(e) => *e,
+            Err(e) => Error::new(ErrorKind::InvalidData, e.to_string()),
+        })
 }
@@ -0,0 +1,328 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// Seconds a turn may last.
+pub const ROUND_TIME_RANGE: RangeInclusive<i64> = 30..=300;
+/// Number of players a lobby may be limited to.
+pub const MAX_PLAYERS_RANGE: RangeInclusive<usize> = 2..=16;
+/// Number of rounds a match may have.
+pub const ROUNDS_RANGE: RangeInclusive<u32> = 1..=10;
+/// Number of words the drawer may be offered to choose from.
+pub const WORD_CHOICES_RANGE: RangeInclusive<usize> = 1..=5;
+/// Number of custom words the host of a lobby may upload.
+pub const MAX_CUSTOM_WORDS: usize = 500;
+/// Number of characters a custom word may have.
+pub const CUSTOM_WORD_LENGTH_RANGE: RangeInclusive<usize> = 2..=30;
+
+/// How hard the words are to draw and to guess.
+#[derive(Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Hash, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Medium,
+    Hard,
+}
+
+/// A word pack of the server as offered to the host of a lobby.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WordPackInfo {
+    pub name: String,
+    /// The language of the words.
+    pub language: String,
+    /// The categories the words belong to.
+    pub categories: Vec<String>,
+    /// The number of words in the pack.
+    pub words: usize,
+}
+
+/// How letters of the word are revealed to the guessers during a turn.
+#[derive(Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum HintSchedule {
+    /// No letters are revealed.
+    Off,
+    /// A few letters are revealed towards the end of the turn.
+    Slow,
+    /// Letters are revealed evenly over the turn.
+    Normal,
+    /// Most letters are revealed before the turn ends.
+    Fast,
+}
+
+impl HintSchedule {
+    /// The number of letters of a word revealed by the end of a turn.
+    ///
+    /// # Arguments
+    /// * `letters` - The number of letters in the word.
+    pub fn hint_count(&self, letters: usize) -> usize {
+        match self {
+            HintSchedule::Off => 0,
+            HintSchedule::Slow => letters / 4,
+            HintSchedule::Normal => letters / 3,
+            HintSchedule::Fast => letters / 2,
+        }
+    }
+
+    /// The share of a turn that passes before the first letter is revealed.
+    fn first_hint_at(&self) -> f64 {
+        match self {
+            HintSchedule::Off => 1.0,
+            HintSchedule::Slow => 0.5,
+            HintSchedule::Normal => 0.3,
+            HintSchedule::Fast => 0.1,
+        }
+    }
+
+    /// The number of letters that should be revealed at a point of a turn.
+    /// After the first hint the remaining hints are spread evenly over the rest of the turn.
+    ///
+    /// # Arguments
+    /// * `letters` - The number of letters in the word.
+    /// * `elapsed` - Seconds since the drawer started drawing.
+    /// * `round_time` - Seconds a turn lasts.
+    pub fn hints_due(&self, letters: usize, elapsed: i64, round_time: i64) -> usize {
+        let count = self.hint_count(letters);
+        if count == 0 || round_time <= 0 {
+            return 0;
+        }
+        let first_hint = self.first_hint_at() * round_time as f64;
+        if (elapsed as f64) < first_hint {
+            return 0;
+        }
+        let interval = (round_time as f64 - first_hint) / count as f64;
+        (1 + ((elapsed as f64 - first_hint) / interval) as usize).min(count)
+    }
+}
+
+/// How guessers are rewarded for guessing the word.
+#[derive(Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum GuesserScoring {
+    /// Every correct guess is worth the same.
+    Equal,
+    /// Faster guesses are worth more.
+    TimeBased,
+    /// Every later guesser gets the same amount less than the one before.
+    LinearlyDecreasing,
+    /// Every later guesser gets a fixed share less than the one before.
+    ExponentiallyDecreasing,
+}
+
+/// How the drawer is rewarded for the players guessing the word.
+#[derive(Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DrawerScoring {
+    /// Every correct guess earns the drawer the same.
+    Equal,
+    /// Faster guesses earn the drawer more.
+    TimeBased,
+    /// Later guesses earn the drawer more than earlier ones.
+    ExponentiallyIncreasing,
+}
+
+/// The rules of the games played in a lobby, chosen by its host.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct LobbySettings {
+    /// Seconds a turn lasts.
+    pub round_time: i64,
+    /// Number of players the lobby accepts.
+    pub max_players: usize,
+    /// Number of rounds in a match.
+    pub rounds: u32,
+    /// Number of words the drawer chooses from at the start of a turn.
+    pub word_choices: usize,
+    /// The names of the word packs the words are taken from.
+    pub word_packs: Vec<String>,
+    /// The difficulties of the words that are played.
+    pub difficulties: Vec<Difficulty>,
+    /// Whether only the custom words of the lobby are played instead of mixing them into the word packs.
+    pub only_custom_words: bool,
+    /// Whether the plural or singular of the word counts as a correct guess.
+    pub accept_plurals: bool,
+    /// Whether the other spellings the word packs list for a word count as a correct guess.
+    pub accept_aliases: bool,
+    pub hint_schedule: HintSchedule,
+    pub guesser_scoring: GuesserScoring,
+    pub drawer_scoring: DrawerScoring,
+}
+
+impl LobbySettings {
+    /// The settings of a new lobby playing with words of every difficulty from the given word packs.
+    ///
+    /// # Arguments
+    /// * `word_packs` - The names of the word packs.
+    pub fn new(word_packs: Vec<String>) -> Self {
+        LobbySettings {
+            round_time: 120,
+            max_players: 10,
+            rounds: 3,
+            word_choices: 3,
+            word_packs,
+            difficulties: vec![Difficulty::Easy, Difficulty::Medium, Difficulty::Hard],
+            only_custom_words: false,
+            accept_plurals: true,
+            accept_aliases: true,
+            hint_schedule: HintSchedule::Normal,
+            guesser_scoring: GuesserScoring::TimeBased,
+            drawer_scoring: DrawerScoring::Equal,
+        }
+    }
+
+    /// Checks that the values are within the limits every lobby enforces.
+    ///
+    /// # Returns
+    /// * `Ok(())` - The settings are valid.
+    /// * `Err(error)` - The first value that is out of range.
+    ///
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        if !ROUND_TIME_RANGE.contains(&self.round_time) {
+            Err(SettingsError::RoundTimeOutOfRange)
+        } else if !MAX_PLAYERS_RANGE.contains(&self.max_players) {
+            Err(SettingsError::MaxPlayersOutOfRange)
+        } else if !ROUNDS_RANGE.contains(&self.rounds) {
+            Err(SettingsError::RoundsOutOfRange)
+        } else if !WORD_CHOICES_RANGE.contains(&self.word_choices) {
+            Err(SettingsError::WordChoicesOutOfRange)
+        } else if self.only_custom_words {
+            Ok(())
+        } else if self.word_packs.is_empty() {
+            Err(SettingsError::NoWordPack)
+        } else if self.difficulties.is_empty() {
+            Err(SettingsError::NoDifficulty)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Why a lobby refused new settings.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SettingsError {
+    /// Only the host may change the settings.
+    NotHost,
+    /// The settings cannot change while a game is running.
+    InGame,
+    RoundTimeOutOfRange,
+    MaxPlayersOutOfRange,
+    RoundsOutOfRange,
+    WordChoicesOutOfRange,
+    /// More players are in the lobby than the new limit allows.
+    TooManyPlayers,
+    /// At least one word pack has to be chosen.
+    NoWordPack,
+    /// At least one difficulty has to be chosen.
+    NoDifficulty,
+    /// The server does not have the word pack.
+    UnknownWordPack,
+    /// The chosen word packs have no words of the chosen difficulties.
+    NoWords,
+    /// Only custom words should be played but none were uploaded.
+    NoCustomWords,
+    /// More custom words were uploaded than a lobby keeps.
+    TooManyCustomWords,
+    /// A custom word is too short or too long.
+    CustomWordLength(String),
+    /// A custom word has characters other than letters, spaces, hyphens and apostrophes.
+    InvalidCustomWord(String),
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::NotHost => write!(f, "only the host can change the settings"),
+            SettingsError::InGame => write!(f, "the settings cannot change during a game"),
+            SettingsError::RoundTimeOutOfRange => write!(
+                f,
+                "the round time has to be between {} and {} seconds",
+                ROUND_TIME_RANGE.start(),
+                ROUND_TIME_RANGE.end()
+            ),
+            SettingsError::MaxPlayersOutOfRange => write!(
+                f,
+                "a lobby can be limited to between {} and {} players",
+                MAX_PLAYERS_RANGE.start(),
+                MAX_PLAYERS_RANGE.end()
+            ),
+            SettingsError::RoundsOutOfRange => write!(
+                f,
+                "a match has between {} and {} rounds",
+                ROUNDS_RANGE.start(),
+                ROUNDS_RANGE.end()
+            ),
+            SettingsError::WordChoicesOutOfRange => write!(
+                f,
+                "the drawer can choose from between {} and {} words",
+                WORD_CHOICES_RANGE.start(),
+                WORD_CHOICES_RANGE.end()
+            ),
+            SettingsError::TooManyPlayers => {
+                write!(f, "more players are in the lobby than the new limit")
+            }
+            SettingsError::NoWordPack => write!(f, "at least one word pack has to be chosen"),
+            SettingsError::NoDifficulty => write!(f, "at least one difficulty has to be chosen"),
+            SettingsError::UnknownWordPack => write!(f, "the server does not have this word pack"),
+            SettingsError::NoWords => write!(
+                f,
+                "the chosen word packs have no words of the chosen difficulties"
+            ),
+            SettingsError::NoCustomWords => write!(f, "no custom words were uploaded"),
+            SettingsError::TooManyCustomWords => {
+                write!(f, "a lobby keeps at most {} custom words", MAX_CUSTOM_WORDS)
+            }
+            SettingsError::CustomWordLength(word) => write!(
+                f,
+                "\"{}\" has to have between {} and {} characters",
+                word,
+                CUSTOM_WORD_LENGTH_RANGE.start(),
+                CUSTOM_WORD_LENGTH_RANGE.end()
+            ),
+            SettingsError::InvalidCustomWord(word) => write!(
+                f,
+                "\"{}\" may only have letters, spaces, hyphens and apostrophes",
+                word
+            ),
+        }
+    }
+}
+
+/// Cleans up the custom words uploaded by the host of a lobby.
+/// Surrounding and repeated whitespace is removed, blank words are skipped
+/// and words repeated in another case are only kept once.
+///
+/// # Arguments
+/// * `words` - The words as typed by the host.
+///
+/// # Returns
+/// * `Ok(words)` - The cleaned up words, empty if there were none.
+/// * `Err(error)` - The first word that is not allowed, or too many words.
+///
+pub fn clean_custom_words(words: &[String]) -> Result<Vec<String>, SettingsError> {
+    let mut cleaned: Vec<String> = Vec::new();
+    let mut known: HashSet<String> = HashSet::new();
+    for word in words {
+        let word = word.split_whitespace().collect::<Vec<_>>().join(" ");
+        if word.is_empty() {
+            continue;
+        }
+        if !CUSTOM_WORD_LENGTH_RANGE.contains(&word.chars().count()) {
+            return Err(SettingsError::CustomWordLength(word));
+        }
+        if !word
+            .chars()
+            .all(|c| c.is_alphabetic() || c == ' ' || c == '-' || c == '\'')
+        {
+            return Err(SettingsError::InvalidCustomWord(word));
+        }
+        if known.insert(word.to_lowercase()) {
+            if cleaned.len() == MAX_CUSTOM_WORDS {
+                return Err(SettingsError::TooManyCustomWords);
+            }
+            cleaned.push(word);
+        }
+    }
+    Ok(cleaned)
+}
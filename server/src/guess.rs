@@ -0,0 +1,286 @@
+use edit_distance::edit_distance;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Letters an answer needs per allowed typo for a guess to count as almost right.
+const LETTERS_PER_TYPO: usize = 4;
+/// The most typos a guess may have to still count as almost right.
+pub(crate) const MAX_TYPOS: usize = 3;
+/// The fewest letters left of a word after removing a plural ending.
+const MIN_SINGULAR_LETTERS: usize = 3;
+
+/// Which other forms of the word are accepted as a correct guess.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct GuessRules {
+    /// English plural and singular forms of the word are accepted.
+    pub accept_plurals: bool,
+    /// The aliases of the word are accepted.
+    pub accept_aliases: bool,
+}
+
+/// How close a guess is to the word.
+#[derive(Eq, PartialEq, Debug)]
+pub(crate) enum GuessMatch {
+    Correct,
+    /// A few letters away from the word.
+    Almost,
+    Wrong,
+}
+
+/// Compares a guess with the word of the turn.
+/// Case, accents, punctuation and whitespace do not matter,
+/// so "Palm-Trees!" and "palmtrees" are both the same as "palm trees".
+///
+/// # Arguments
+/// * `guess` - The guess as typed by the player.
+/// * `word` - The word of the turn.
+/// * `aliases` - The other spellings of the word.
+/// * `rules` - Which other forms of the word are accepted.
+///
+/// # Returns
+/// * `GuessMatch` - Whether the guess is correct, almost correct or wrong.
+pub(crate) fn check_guess(
+    guess: &str,
+    word: &str,
+    aliases: &[String],
+    rules: GuessRules,
+) -> GuessMatch {
+    let guess = normalize(guess);
+    if guess.is_empty() {
+        return GuessMatch::Wrong;
+    }
+    let aliases = if rules.accept_aliases { aliases } else { &[] };
+    let answers: Vec<String> = std::iter::once(word)
+        .chain(aliases.iter().map(String::as_str))
+        .map(normalize)
+        .filter(|answer| !answer.is_empty())
+        .collect();
+    let correct = answers.iter().any(|answer| {
+        if rules.accept_plurals {
+            let answer_forms = singular_forms(answer);
+            singular_forms(&guess)
+                .iter()
+                .any(|form| answer_forms.contains(form))
+        } else {
+            *answer == guess
+        }
+    });
+    if correct {
+        GuessMatch::Correct
+    } else if answers
+        .iter()
+        .any(|answer| edit_distance(answer, &guess) <= allowed_typos(answer))
+    {
+        GuessMatch::Almost
+    } else {
+        GuessMatch::Wrong
+    }
+}
+
+/// Reduces a text to its lowercase letters and digits.
+/// Accents are removed and characters like ligatures or full width letters are replaced by their plain form.
+///
+/// # Arguments
+/// * `text` - The text to normalize.
+pub(crate) fn normalize(text: &str) -> String {
+    text.nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .flat_map(|c| match c {
+            'ß' => vec!['s', 's'],
+            c => vec![c],
+        })
+        .filter(|c| c.is_alphanumeric())
+        .collect()
+}
+
+/// The forms a normalized word may have without an English plural ending, including the word itself.
+///
+/// # Arguments
+/// * `word` - A normalized word.
+fn singular_forms(word: &str) -> Vec<String> {
+    let mut forms = vec![word.to_string()];
+    let mut add = |stem: &str, ending: &str| {
+        if stem.chars().count() >= MIN_SINGULAR_LETTERS {
+            forms.push(format!("{}{}", stem, ending));
+        }
+    };
+    if let Some(stem) = word.strip_suffix("ies") {
+        add(stem, "y");
+    }
+    if let Some(stem) = word.strip_suffix("es") {
+        add(stem, "");
+    }
+    if let Some(stem) = word.strip_suffix('s') {
+        if !stem.ends_with('s') {
+            add(stem, "");
+        }
+    }
+    forms
+}
+
+/// The number of typos a guess of an answer may have to count as almost right, longer answers allow more.
+///
+/// # Arguments
+/// * `answer` - A normalized answer.
+fn allowed_typos(answer: &str) -> usize {
+    (answer.chars().count() / LETTERS_PER_TYPO).clamp(1, MAX_TYPOS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: GuessRules = GuessRules {
+        accept_plurals: true,
+        accept_aliases: true,
+    };
+    const STRICT: GuessRules = GuessRules {
+        accept_plurals: false,
+        accept_aliases: false,
+    };
+
+    fn check(guess: &str, word: &str, rules: GuessRules) -> GuessMatch {
+        check_guess(guess, word, &[], rules)
+    }
+
+    #[test]
+    fn normalize_folds_case_accents_punctuation_and_whitespace() {
+        assert_eq!(normalize("Palm-Trees!"), "palmtrees");
+        assert_eq!(normalize("  palm \t trees "), "palmtrees");
+        assert_eq!(normalize("Chamäleon"), "chamaleon");
+        assert_eq!(normalize("CRÈME brûlée"), "cremebrulee");
+        assert_eq!(normalize("Kevin's Mug"), "kevinsmug");
+        assert_eq!(normalize("Straße"), "strasse");
+        assert_eq!(normalize("ＰＡＬＭ"), "palm");
+        assert_eq!(normalize("?!. -"), "");
+    }
+
+    #[test]
+    fn exact_guesses_are_correct() {
+        for rules in [ALL, STRICT] {
+            assert_eq!(
+                check("palm trees", "palm trees", rules),
+                GuessMatch::Correct
+            );
+            assert_eq!(
+                check("PALM TREES", "palm trees", rules),
+                GuessMatch::Correct
+            );
+        }
+    }
+
+    #[test]
+    fn spacing_and_punctuation_do_not_matter() {
+        for guess in ["palmtrees", "Palm-Trees!", "palm  trees", " palm.trees "] {
+            assert_eq!(check(guess, "palm trees", STRICT), GuessMatch::Correct);
+        }
+        assert_eq!(
+            check("pizza friday", "pizza-friday", STRICT),
+            GuessMatch::Correct
+        );
+    }
+
+    #[test]
+    fn accents_do_not_matter() {
+        assert_eq!(check("chamaleon", "Chamäleon", STRICT), GuessMatch::Correct);
+        assert_eq!(
+            check("Eichhornchen", "Eichhörnchen", STRICT),
+            GuessMatch::Correct
+        );
+        assert_eq!(check("café", "cafe", STRICT), GuessMatch::Correct);
+    }
+
+    #[test]
+    fn plurals_are_accepted_when_enabled() {
+        let cases = [
+            ("palm tree", "palm trees"),
+            ("palm trees", "palm tree"),
+            ("sunflower", "sunflowers"),
+            ("box", "boxes"),
+            ("puppy", "puppies"),
+            ("puppies", "puppy"),
+            ("shoe", "shoes"),
+            ("tomato", "tomatoes"),
+        ];
+        for (guess, word) in cases {
+            assert_eq!(check(guess, word, ALL), GuessMatch::Correct, "{}", guess);
+            assert_ne!(check(guess, word, STRICT), GuessMatch::Correct, "{}", guess);
+        }
+    }
+
+    #[test]
+    fn only_plausible_plural_endings_are_removed() {
+        assert_ne!(check("ga", "gas", ALL), GuessMatch::Correct);
+        assert_ne!(check("glas", "glass", ALL), GuessMatch::Correct);
+    }
+
+    #[test]
+    fn aliases_are_accepted_when_enabled() {
+        let aliases = ["muffin".to_string()];
+        assert_eq!(
+            check_guess("Muffin", "cupcake", &aliases, ALL),
+            GuessMatch::Correct
+        );
+        assert_eq!(
+            check_guess("muffins", "cupcake", &aliases, ALL),
+            GuessMatch::Correct
+        );
+        assert_eq!(
+            check_guess("muffin", "cupcake", &aliases, STRICT),
+            GuessMatch::Wrong
+        );
+    }
+
+    #[test]
+    fn almost_threshold_scales_with_the_word_length() {
+        // short words allow a single typo
+        assert_eq!(check("bat", "cat", STRICT), GuessMatch::Almost);
+        assert_eq!(check("bag", "cat", STRICT), GuessMatch::Wrong);
+        // eight letters allow two typos
+        assert_eq!(check("elefant", "elephant", STRICT), GuessMatch::Almost);
+        assert_eq!(check("elefent", "elephant", STRICT), GuessMatch::Wrong);
+        // long words allow three typos at most
+        assert_eq!(
+            check("hipopotomos", "hippopotamus", STRICT),
+            GuessMatch::Almost
+        );
+        assert_eq!(
+            check("hipopotomoz", "hippopotamus", STRICT),
+            GuessMatch::Wrong
+        );
+        assert_eq!(
+            check("ambulanze kranknwagon", "ambulance krankenwagen", STRICT),
+            GuessMatch::Almost
+        );
+        assert_eq!(
+            check("ambulanze kranknwagonn", "ambulance krankenwagen", STRICT),
+            GuessMatch::Wrong
+        );
+    }
+
+    #[test]
+    fn almost_is_measured_against_aliases_too() {
+        let aliases = ["koala".to_string()];
+        assert_eq!(
+            check_guess("koalla", "koala bear", &aliases, ALL),
+            GuessMatch::Almost
+        );
+        assert_eq!(
+            check_guess("koalla", "koala bear", &aliases, STRICT),
+            GuessMatch::Wrong
+        );
+    }
+
+    #[test]
+    fn empty_guesses_are_wrong() {
+        assert_eq!(check("", "cat", ALL), GuessMatch::Wrong);
+        assert_eq!(check("?!", "cat", ALL), GuessMatch::Wrong);
+    }
+
+    #[test]
+    fn other_words_are_wrong() {
+        assert_eq!(check("dog", "palm trees", ALL), GuessMatch::Wrong);
+        assert_eq!(check("palm", "palm trees", ALL), GuessMatch::Wrong);
+    }
+}
@@ -0,0 +1,55 @@
+use std::fs::{self, OpenOptions};
+use std::io::{Error, ErrorKind, Write};
+use std::path::Path;
+
+use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+use rand_core::OsRng;
+use rust_scribble_common::network_common::fingerprint;
+
+/// Loads the long-term identity key of the server, or creates and stores a new one
+/// if the file does not exist yet.
+///
+/// Clients pin the public part of this key on their first connection,
+/// so the file has to be kept across restarts of the server.
+///
+/// # Arguments
+/// * `path` - The file containing the 32 byte secret key.
+///
+/// # Returns
+/// * `Ok(keypair)` - The identity keypair of the server.
+/// * `Err(e)` - The file could not be read, written or does not contain a valid key.
+pub fn load_or_create_identity(path: impl AsRef<Path>) -> Result<Keypair, Error> {
+    let path = path.as_ref();
+    let keypair = if path.exists() {
+        let secret = SecretKey::from_bytes(&fs::read(path)?).map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("{} does not contain a valid identity key", path.display()),
+            )
+        })?;
+        let public = PublicKey::from(&secret);
+        Keypair { secret, public }
+    } else {
+        let keypair = Keypair::generate(&mut OsRng);
+        write_secret_key(path, keypair.secret.as_bytes())?;
+        println!("Created new server identity in {}", path.display());
+        keypair
+    };
+    println!(
+        "Server identity fingerprint: {}",
+        fingerprint(keypair.public.as_bytes())
+    );
+    Ok(keypair)
+}
+
+/// Writes the secret key to a new file that only the owner may read.
+fn write_secret_key(path: &Path, secret: &[u8]) -> Result<(), Error> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(secret)
+}
@@ -0,0 +1,288 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rand::Rng;
+use rust_scribble_common::handshake_common::{LobbyInfo, LobbyRequest, LobbySummary, RejectReason};
+use rust_scribble_common::settings_common::LobbySettings;
+use tokio::sync::mpsc;
+use tokio::time::{self, Instant};
+
+use crate::wordpack::WordPack;
+use crate::{network, LobbyState};
+
+/// Number of players matchmaking fills a public lobby up to.
+const OPTIMAL_LOBBY_SIZE: usize = 5;
+/// Characters join codes are made of, without the ones that are easily confused like O and 0.
+const JOIN_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const JOIN_CODE_LENGTH: usize = 6;
+/// Seconds between two checks for idle lobbies.
+const IDLE_CHECK_INTERVAL: u64 = 5;
+
+/// A lobby known to the registry.
+struct RegisteredLobby {
+    name: String,
+    /// The code needed to join a private lobby, public lobbies have none.
+    join_code: Option<String>,
+    state: Arc<Mutex<LobbyState>>,
+    /// The last time the lobby was seen with players or handed to a joining client.
+    last_active: Instant,
+}
+
+impl RegisteredLobby {
+    fn player_count(&self) -> usize {
+        self.state.lock().unwrap().players().lock().unwrap().len()
+    }
+
+    /// The number of players the host allows in the lobby.
+    fn capacity(&self) -> usize {
+        self.state.lock().unwrap().settings().max_players
+    }
+}
+
+/// The lobbies of a server by id, private lobbies can also be found by their join code.
+pub(crate) struct LobbyRegistry {
+    /// The word packs of the server.
+    word_packs: Arc<Vec<WordPack>>,
+    /// The names of the word packs every new lobby starts with.
+    default_word_packs: Vec<String>,
+    next_lobby_id: u64,
+    lobbies: BTreeMap<u64, RegisteredLobby>,
+    /// The ids of the private lobbies by join code.
+    join_codes: HashMap<String, u64>,
+}
+
+impl LobbyRegistry {
+    /// Creates an empty registry.
+    /// New lobbies play with the word packs in the given language, or with all packs if none is in that language.
+    ///
+    /// # Arguments
+    /// * `word_packs` - The word packs of the server.
+    /// * `language` - The language new lobbies start with.
+    pub(crate) fn new(word_packs: Vec<WordPack>, language: &str) -> Self {
+        let mut default_word_packs: Vec<String> = word_packs
+            .iter()
+            .filter(|pack| pack.language.eq_ignore_ascii_case(language))
+            .map(|pack| pack.name.clone())
+            .collect();
+        if default_word_packs.is_empty() {
+            default_word_packs = word_packs.iter().map(|pack| pack.name.clone()).collect();
+        }
+        LobbyRegistry {
+            word_packs: Arc::new(word_packs),
+            default_word_packs,
+            next_lobby_id: 1,
+            lobbies: BTreeMap::new(),
+            join_codes: HashMap::new(),
+        }
+    }
+
+    /// Finds or creates the lobby a client asked for in its hello.
+    ///
+    /// # Arguments
+    /// * `request` - The lobby requested by the client.
+    /// * `username` - The name of the player, used to name private lobbies without a name.
+    ///
+    /// # Returns
+    /// * `Ok((info, lobby))` - The description of the lobby for the client and the lobby itself.
+    /// * `Err(reason)` - The lobby does not exist or is full.
+    ///
+    pub(crate) fn resolve(
+        &mut self,
+        request: &LobbyRequest,
+        username: &str,
+    ) -> Result<(LobbyInfo, Arc<Mutex<LobbyState>>), RejectReason> {
+        let id = match request {
+            LobbyRequest::Matchmaking => self.find_public_lobby(),
+            LobbyRequest::Lobby { id } => match self.lobbies.get(id) {
+                Some(lobby) if lobby.join_code.is_none() => *id,
+                _ => return Err(RejectReason::UnknownLobby),
+            },
+            LobbyRequest::CreatePrivate { name } => {
+                let name = match name.trim() {
+                    "" => format!("{}'s lobby", username.trim()),
+                    name => name.to_string(),
+                };
+                let join_code = self.generate_join_code();
+                self.setup_new_lobby(name, Some(join_code))
+            }
+            LobbyRequest::JoinCode { code } => *self
+                .join_codes
+                .get(&code.trim().to_uppercase())
+                .ok_or(RejectReason::UnknownJoinCode)?,
+            // browsing clients are answered before a lobby is looked up
+            LobbyRequest::Browse => return Err(RejectReason::UnknownLobby),
+        };
+        let lobby = self.lobbies.get_mut(&id).unwrap();
+        if lobby.player_count() >= lobby.capacity() {
+            return Err(RejectReason::LobbyFull);
+        }
+        // the joining client is not counted until its handshake is done,
+        // the lobby turns it away if others filled it in the meantime
+        lobby.last_active = Instant::now();
+        let info = LobbyInfo::new(
+            id,
+            lobby.name.clone(),
+            lobby.join_code.clone(),
+            self.word_packs.iter().map(|pack| pack.info()).collect(),
+        );
+        Ok((info, lobby.state.clone()))
+    }
+
+    /// Describes the public lobbies for the lobby browser.
+    ///
+    /// The registry is only locked to take a snapshot of the lobbies,
+    /// afterwards the lobbies are locked one at a time to read their state.
+    ///
+    /// # Arguments
+    /// * `registry` - The registry of the server.
+    ///
+    /// # Returns
+    /// * `lobbies` - The public lobbies ordered by id.
+    pub(crate) fn list_public_lobbies(registry: &Mutex<LobbyRegistry>) -> Vec<LobbySummary> {
+        let lobbies: Vec<_> = registry
+            .lock()
+            .unwrap()
+            .lobbies
+            .iter()
+            .filter(|(_, lobby)| lobby.join_code.is_none())
+            .map(|(id, lobby)| (*id, lobby.name.clone(), lobby.state.clone()))
+            .collect();
+        lobbies
+            .into_iter()
+            .map(|(id, name, state)| {
+                let state = state.lock().unwrap();
+                let players = state.players().lock().unwrap().len();
+                let in_game = state.game_state().lock().unwrap().in_game;
+                let settings = state.settings();
+                LobbySummary {
+                    id,
+                    name,
+                    players,
+                    capacity: settings.max_players,
+                    in_game,
+                    languages: state.languages(),
+                    word_packs: if settings.only_custom_words {
+                        Vec::new()
+                    } else {
+                        settings.word_packs
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// Closes the lobbies that have been empty for the given time and forgets them.
+    ///
+    /// # Arguments
+    /// * `idle_timeout` - How long a lobby may stay without players.
+    pub(crate) fn close_idle_lobbies(&mut self, idle_timeout: Duration) {
+        let now = Instant::now();
+        let mut idle = Vec::new();
+        for (id, lobby) in self.lobbies.iter_mut() {
+            if !lobby.state.lock().unwrap().is_empty() {
+                lobby.last_active = now;
+            } else if now.duration_since(lobby.last_active) >= idle_timeout {
+                idle.push(*id);
+            }
+        }
+        for id in idle {
+            let lobby = self.lobbies.remove(&id).unwrap();
+            if let Some(code) = &lobby.join_code {
+                self.join_codes.remove(code);
+            }
+            lobby.state.lock().unwrap().close();
+            println!(
+                "Closed lobby {} \"{}\" after it stayed empty",
+                id, lobby.name
+            );
+        }
+    }
+
+    /// Returns the id of the first public lobby with room, a new public lobby is opened if all are full.
+    fn find_public_lobby(&mut self) -> u64 {
+        let lobby = self.lobbies.iter().find(|(_, lobby)| {
+            lobby.join_code.is_none()
+                && lobby.player_count() < OPTIMAL_LOBBY_SIZE.min(lobby.capacity())
+        });
+        match lobby {
+            Some((id, _)) => *id,
+            None => {
+                let name = format!("Lobby {}", self.next_lobby_id);
+                self.setup_new_lobby(name, None)
+            }
+        }
+    }
+
+    /// Generates a join code that is not used by any other lobby.
+    fn generate_join_code(&self) -> String {
+        let mut rng = rand::thread_rng();
+        loop {
+            let code: String = (0..JOIN_CODE_LENGTH)
+                .map(|_| JOIN_CODE_ALPHABET[rng.gen_range(0, JOIN_CODE_ALPHABET.len())] as char)
+                .collect();
+            if !self.join_codes.contains_key(&code) {
+                return code;
+            }
+        }
+    }
+
+    /// Creates a new lobby and starts the task acting as the lobby.
+    ///
+    /// # Arguments
+    /// * `name` - The name shown to the players.
+    /// * `join_code` - The code to join the lobby with, none for a public lobby.
+    ///
+    /// # Returns
+    /// * `id` - The id of the new lobby.
+    fn setup_new_lobby(&mut self, name: String, join_code: Option<String>) -> u64 {
+        let id = self.next_lobby_id;
+        self.next_lobby_id += 1;
+        match &join_code {
+            Some(code) => println!("Setting up private lobby {} \"{}\" ({})", id, name, code),
+            None => println!("Setting up public lobby {} \"{}\"", id, name),
+        }
+        let (lobby_tx, lobby_rx) = mpsc::channel(network::LOBBY_QUEUE_SIZE);
+        let state = Arc::new(Mutex::new(LobbyState::default(
+            self.word_packs.clone(),
+            LobbySettings::new(self.default_word_packs.clone()),
+            lobby_tx,
+        )));
+        // Spawn a new task acting as the lobby
+        tokio::spawn(network::check_send_broadcast_messages(
+            state.clone(),
+            lobby_rx,
+        ));
+        if let Some(code) = &join_code {
+            self.join_codes.insert(code.clone(), id);
+        }
+        self.lobbies.insert(
+            id,
+            RegisteredLobby {
+                name,
+                join_code,
+                state,
+                last_active: Instant::now(),
+            },
+        );
+        id
+    }
+}
+
+/// Closes the lobbies that stay empty for too long.
+/// This function runs in its own task and loops indefinitely.
+///
+/// # Arguments
+/// * `registry` - The registry of the server.
+/// * `idle_timeout` - How long a lobby may stay without players.
+///
+pub(crate) async fn close_idle_lobbies(
+    registry: Arc<Mutex<LobbyRegistry>>,
+    idle_timeout: Duration,
+) {
+    let mut check = time::interval(Duration::from_secs(IDLE_CHECK_INTERVAL));
+    loop {
+        check.tick().await;
+        registry.lock().unwrap().close_idle_lobbies(idle_timeout);
+    }
+}
@@ -1,121 +1,189 @@
 use std::collections::BTreeMap;
-use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
-use std::thread;
 use std::time::Duration;
 
 use delegate::delegate;
-use edit_distance::edit_distance;
-use parking_lot::{Condvar as PLCondvar, Mutex as PLMutex};
 use rand::Rng;
 use rust_scribble_common::gamestate_common::*;
-use rust_scribble_common::messages_common::GameStateUpdate;
-use serde_json::{json, Value};
+use rust_scribble_common::messages_common::{
+    Audience, ChatChannel, EventMessage, GameEvent, GameStateUpdate, Message,
+};
+use rust_scribble_common::settings_common::{self, LobbySettings, SettingsError};
+use tokio::sync::{mpsc, watch};
+use tokio::time::{self, Instant};
 
-use crate::rewardstrategy::{RewardStrategyDrawer, RewardStrategyGuesser};
+use crate::guess::{self, GuessMatch, GuessRules};
+use crate::moderation::{self, ChatModerator, ChatVerdict};
+use crate::rewardstrategy::{self, RewardStrategyDrawer, RewardStrategyGuesser};
+use crate::worddeck::WordDeck;
+use crate::wordpack::{self, WordEntry, WordPack};
 
 pub(crate) const MIN_NUMBER_PLAYERS: usize = 2;
-pub(crate) const GAME_TIME: i64 = 120;
-// seconds
-const MAX_ALLOWED_EDIT_DISTANCE_FOR_ALMOST: usize = 2;
+/// Seconds the drawer has to choose a word before one is picked for them.
+const WORD_CHOICE_TIME: i64 = 10;
+/// Seconds the word and the points of a turn are shown before the next turn starts.
+const TURN_SUMMARY_TIME: i64 = 5;
 
 pub struct LobbyState {
     state: Arc<Mutex<LobbyStateInner>>,
-    started_lock: Arc<(PLMutex<bool>, PLCondvar)>,
+    started: Arc<Mutex<bool>>,
+    /// Set once the lobby is closed, stops every task of the lobby.
+    shutdown: watch::Sender<bool>,
+}
+
+/// Waits until the lobby is closed.
+///
+/// # Arguments
+/// * `shutdown` - The shutdown signal of the lobby.
+///
+pub(crate) async fn closed(shutdown: &mut watch::Receiver<bool>) {
+    while !*shutdown.borrow_and_update() {
+        // a dropped lobby counts as closed
+        if shutdown.changed().await.is_err() {
+            return;
+        }
+    }
 }
 
 impl LobbyState {
     pub fn default(
-        words: Vec<String>,
-        reward_strategy_guesser: &'static dyn RewardStrategyGuesser,
-        reward_strategy_drawer: &'static dyn RewardStrategyDrawer,
-        lobby_tx: mpsc::Sender<Value>,
+        word_packs: Arc<Vec<WordPack>>,
+        settings: LobbySettings,
+        lobby_tx: mpsc::Sender<Message>,
     ) -> Self {
         LobbyState {
             state: Arc::new(Mutex::new(LobbyStateInner::default(
-                words,
-                reward_strategy_guesser,
-                reward_strategy_drawer,
-                lobby_tx,
+                word_packs, settings, lobby_tx,
             ))),
-            started_lock: Arc::new((PLMutex::new(false), PLCondvar::new())),
+            started: Arc::new(Mutex::new(false)),
+            shutdown: watch::channel(false).0,
         }
     }
 
+    /// Closes the lobby, its message handling and timer tasks stop.
+    pub fn close(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Returns the signal the tasks of the lobby stop on.
+    pub fn shutdown_signal(&self) -> watch::Receiver<bool> {
+        self.shutdown.subscribe()
+    }
+
     pub fn start_game_on_timer(&mut self, secs: u64) {
         let local_state = self.state.clone();
-        let local_started = self.started_lock.clone();
+        let local_started = self.started.clone();
         let tx = self.lobby_tx();
+        let mut shutdown = self.shutdown_signal();
 
-        thread::spawn(move || {
-            println!("Init startup thread with {} secs", &secs);
-            let (lock, cvar) = &*local_started;
-            let mut started = lock.lock();
-
-            // no spurious wakeups in parking_lot cvars
-            cvar.wait_for(&mut started, Duration::from_secs(secs));
-            if !(*started) && local_state.lock().unwrap().all_ready() {
-                local_state.lock().unwrap().start_game();
-                Self::start_timer_thread(local_state.clone(), tx.clone());
-                *started = true;
-                tx.send(json!({"kind": "update_requested"}))
-                    .expect("Lobby has lost channel connection to network!");
-            } // if already true, another startup thread has started the game already
-            cvar.notify_all(); // other startup threads are notified and will terminate as started is already set to true
-            println!(
-                "Debug: Startup Thread with {} secs terminated (early)",
-                secs
-            )
+        tokio::spawn(async move {
+            println!("Init startup task with {} secs", &secs);
+            tokio::select! {
+                _ = time::sleep(Duration::from_secs(secs)) => {}
+                _ = closed(&mut shutdown) => return,
+            }
+            let start = {
+                let mut started = local_started.lock().unwrap();
+                // if already true, another startup task has started the game already,
+                // players may also have left or become unready during the countdown
+                let start = !*started && local_state.lock().unwrap().can_start();
+                if start {
+                    local_state.lock().unwrap().start_match();
+                    *started = true;
+                }
+                start
+            };
+            if start {
+                Self::start_timer_task(local_state, tx.clone(), shutdown);
+                // the lobby has stopped handling messages
+                if tx.send(Message::UpdateRequested).await.is_err() {
+                    return;
+                }
+            } else {
+                println!("Startup task with {} secs cancelled", secs);
+            }
+            println!("Debug: Startup task with {} secs terminated", secs)
         });
     }
 
-    fn start_timer_thread(state_ref: Arc<Mutex<LobbyStateInner>>, lobby_tx: mpsc::Sender<Value>) {
-        let tick = schedule_recv::periodic(Duration::from_secs(1));
-        thread::spawn(move || {
+    fn start_timer_task(
+        state_ref: Arc<Mutex<LobbyStateInner>>,
+        lobby_tx: mpsc::Sender<Message>,
+        mut shutdown: watch::Receiver<bool>,
+    ) {
+        tokio::spawn(async move {
+            let second = Duration::from_secs(1);
+            let mut tick = time::interval_at(Instant::now() + second, second);
             loop {
-                tick.recv().unwrap();
-                let state = state_ref.lock().unwrap();
-                let mut game_state = state.game_state.lock().unwrap();
-                if !game_state.in_game {
-                    break;
+                tokio::select! {
+                    _ = tick.tick() => {}
+                    _ = closed(&mut shutdown) => break,
                 }
-                let new_time = game_state.time - 1;
-                game_state.time = new_time;
+                let game_state = {
+                    let mut state = state_ref.lock().unwrap();
+                    {
+                        let mut game_state = state.game_state.lock().unwrap();
+                        if !game_state.in_game {
+                            break;
+                        }
+                        game_state.time -= 1;
+                    }
+                    state.reveal_hints();
+                    let game_state = state.game_state.lock().unwrap().clone();
+                    game_state
+                };
+                let time_up = game_state.time == 0;
                 // Timer could be implemented clientside to save some network traffic,
                 // but as to not cause problems with client side code at this late stage of the
                 // project I'll implement this workaround for now
-                let _ = lobby_tx.send(json!(GameStateUpdate::new(game_state.clone())));
-                drop(game_state);
-                drop(state);
-                if new_time == 0 {
-                    let mut state = state_ref.lock().unwrap();
-                    state.end_game();
-                    drop(state);
-                    lobby_tx
-                        .send(json!({"kind": "time_up"}))
-                        .expect("Lobby has lost channel connection to network!");
+                // the lobby has stopped handling messages, so there is no game left to time
+                if lobby_tx
+                    .send(Message::GameStateUpdate(GameStateUpdate::new(game_state)))
+                    .await
+                    .is_err()
+                {
                     break;
                 }
+                if time_up {
+                    // the game moves on right away, the task keeps running until the match is over
+                    let match_over = {
+                        let mut state = state_ref.lock().unwrap();
+                        state.time_up();
+                        let in_game = state.game_state.lock().unwrap().in_game;
+                        !in_game
+                    };
+                    if lobby_tx.send(Message::TimeUp).await.is_err() || match_over {
+                        break;
+                    }
+                }
             }
         });
     }
 
     pub fn cleanup_lobby_after_end_game(&mut self) {
-        *self.started_lock.0.lock() = false;
+        *self.started.lock().unwrap() = false;
     }
 
     delegate! {
         to self.state.lock().unwrap() {
-            pub fn add_player(&mut self, id: i64, name: String);
-            pub fn remove_player(&mut self, player_id: i64);
+            pub fn add_player(&mut self, id: i64, name: String) -> bool;
             pub fn set_ready(&mut self, player_id: i64, status: bool);
-            pub fn all_ready(&self) -> bool;
-            pub fn add_line_msg(&mut self, line_msg: Value);
+            pub fn can_start(&self) -> bool;
+            pub fn update_settings(&mut self, player_id: i64, settings: LobbySettings) -> Result<(), SettingsError>;
+            pub fn set_custom_words(&mut self, player_id: i64, words: &[String]) -> Result<usize, SettingsError>;
+            pub fn choose_word(&mut self, player_id: i64, choice: usize);
+            pub fn is_empty(&self) -> bool;
+            pub fn languages(&self) -> Vec<String>;
+            pub fn may_draw(&self, player_id: i64) -> bool;
+            pub fn message_for(&self, client_id: i64, msg: &Message) -> Option<Message>;
+            pub fn add_line_msg(&mut self, line_msg: Message);
+            pub fn moderate_chat(&mut self, player_id: i64, message: &str) -> ChatVerdict;
             pub fn chat_or_correct_guess(&mut self, player_id: i64, message: &str) -> GuessResult;
             pub fn all_guessed(&mut self) -> bool;
-            pub fn add_client_tx(&mut self, id: i64, tx: mpsc::Sender<Value>);
+            pub fn add_client_tx(&mut self, id: i64, tx: mpsc::Sender<Message>);
             pub fn remove_client_tx(&mut self, id: i64);
-            // start_game should not be accessible directly to keep the interface clean.
+            pub fn take_events(&mut self) -> Vec<EventMessage>;
+            // start_match should not be accessible directly to keep the interface clean.
             // A countdown of 0 seconds can be used to start immediately
             // but the game is usually started with some small countdown instead
         }
@@ -128,13 +196,13 @@ impl LobbyState {
     pub fn players(&self) -> Arc<Mutex<Vec<Player>>> {
         self.state.lock().unwrap().players.clone()
     }
-    pub fn _word_list(&self) -> Arc<Mutex<Vec<String>>> {
-        self.state.lock().unwrap().word_list.clone()
+    pub fn settings(&self) -> LobbySettings {
+        self.state.lock().unwrap().settings.clone()
     }
-    pub fn lobby_tx(&self) -> mpsc::Sender<Value> {
+    pub fn lobby_tx(&self) -> mpsc::Sender<Message> {
         self.state.lock().unwrap().lobby_tx.clone()
     }
-    pub fn client_tx(&self) -> BTreeMap<i64, mpsc::Sender<Value>> {
+    pub fn client_tx(&self) -> BTreeMap<i64, mpsc::Sender<Message>> {
         self.state.lock().unwrap().client_txs.clone()
     }
 }
@@ -147,54 +215,78 @@ impl LobbyState {
 struct LobbyStateInner {
     pub game_state: Arc<Mutex<GameState>>,
     pub players: Arc<Mutex<Vec<Player>>>,
-    pub lines: Arc<Mutex<Vec<Value>>>,
-    pub word_list: Arc<Mutex<Vec<String>>>,
-    pub lobby_tx: mpsc::Sender<Value>,
-    pub client_txs: BTreeMap<i64, mpsc::Sender<Value>>,
-    pub reward_strategy_guesser: &'static dyn RewardStrategyGuesser,
-    pub reward_strategy_drawer: &'static dyn RewardStrategyDrawer,
+    pub lines: Arc<Mutex<Vec<Message>>>,
+    pub word_deck: WordDeck,
+    pub lobby_tx: mpsc::Sender<Message>,
+    pub client_txs: BTreeMap<i64, mpsc::Sender<Message>>,
+    /// The ids of the players drawing in the current match, in the order they draw each round.
+    pub drawer_order: Vec<i64>,
+    pub settings: LobbySettings,
+    /// The word packs the host can choose from.
+    pub word_packs: Arc<Vec<WordPack>>,
+    /// The words uploaded by the host, only this lobby plays them.
+    pub custom_words: Vec<String>,
+    pub chat_moderator: ChatModerator,
+    /// The events that happened since they were last sent to the clients.
+    pub events: Vec<EventMessage>,
+    /// The players who guessed the word in the current turn and the points they earned, the first one first.
+    pub turn_guesses: Vec<TurnScore>,
+    /// The points the drawer earned in the current turn.
+    pub drawer_points: i64,
+    pub reward_strategy_guesser: Box<dyn RewardStrategyGuesser>,
+    pub reward_strategy_drawer: Box<dyn RewardStrategyDrawer>,
 }
 
 impl LobbyStateInner {
-    /// Creates a new ServerStateInner with the given word list and tx.
+    /// Creates a new ServerStateInner with the given word packs and tx.
     ///
     /// # Arguments
-    ///   * `words` - The vector word list to use for the game.
+    ///   * `word_packs` - The word packs the host can choose from.
+    ///   * `settings` - The initial settings of the lobby, they decide the words played and how points are awarded for correct guesses.
     ///   * `lobby_tx` - The tx mpsc to send updates to the clients.
-    ///   * `reward_strategy_guesser` - The reward strategy used to award points to guessers.
-    ///   * `reward_strategy_drawer` - The reward strategy used to award points to the drawer.
-    /// It determines how points are awarded for correct guesses.
     pub fn default(
-        words: Vec<String>,
-        reward_strategy_guesser: &'static dyn RewardStrategyGuesser,
-        reward_strategy_drawer: &'static dyn RewardStrategyDrawer,
-        lobby_tx: mpsc::Sender<Value>,
+        word_packs: Arc<Vec<WordPack>>,
+        settings: LobbySettings,
+        lobby_tx: mpsc::Sender<Message>,
     ) -> Self {
+        let words =
+            wordpack::select_words(&word_packs, &settings.word_packs, &settings.difficulties);
+        let reward_strategy_guesser =
+            rewardstrategy::guesser_strategy(settings.guesser_scoring, settings.round_time);
+        let reward_strategy_drawer =
+            rewardstrategy::drawer_strategy(settings.drawer_scoring, settings.round_time);
         LobbyStateInner {
             game_state: Arc::new(Mutex::new(GameState::default())),
             players: Arc::new(Mutex::new(Vec::new())),
             lines: Arc::new(Mutex::new(Vec::new())),
-            word_list: Arc::new(Mutex::new(words)),
+            word_deck: WordDeck::new(words),
             lobby_tx,
             client_txs: BTreeMap::new(),
+            drawer_order: Vec::new(),
+            settings,
+            word_packs,
+            custom_words: Vec::new(),
+            chat_moderator: ChatModerator::default(),
+            events: Vec::new(),
+            turn_guesses: Vec::new(),
+            drawer_points: 0,
             reward_strategy_guesser,
             reward_strategy_drawer,
         }
     }
 
     /// Adds a player' communication channel to the game.
+    /// A client joining a running game receives the lines drawn so far.
     ///
     /// # Arguments
     ///   * `id` - The id of the player.
     ///   * `tx` - The tx mpsc to send updates to the clients.
-    pub fn add_client_tx(&mut self, id: i64, tx: mpsc::Sender<Value>) {
+    pub fn add_client_tx(&mut self, id: i64, tx: mpsc::Sender<Message>) {
         self.client_txs.insert(id, tx.clone());
         if self.game_state.lock().unwrap().in_game {
-            self.lines
-                .lock()
-                .unwrap()
-                .iter()
-                .for_each(|msg| tx.send(msg.clone()).unwrap());
+            self.lines.lock().unwrap().iter().for_each(|msg| {
+                let _ = tx.try_send(msg.clone());
+            });
         }
     }
 
@@ -208,14 +300,32 @@ impl LobbyStateInner {
         self.client_txs.remove(&id);
     }
 
-    /// Adds a player to the game.
+    /// Hands over the events that happened since the last call, to send them to the clients.
+    pub fn take_events(&mut self) -> Vec<EventMessage> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Adds a player to the game, unless the lobby is full.
+    /// The first player of a lobby becomes its host.
     ///
     /// # Arguments
     ///   * `id` - The id of the player.
     ///   * `name` - The name of the player.
     ///
-    pub fn add_player(&mut self, id: i64, name: String) {
-        self.players.lock().unwrap().push(Player::new(id, name));
+    /// # Returns
+    ///  * `true` - If the player was added.
+    ///  * `false` - If the lobby already has `max_players` players.
+    pub fn add_player(&mut self, id: i64, name: String) -> bool {
+        let mut players = self.players.lock().unwrap();
+        if players.len() >= self.settings.max_players {
+            return false;
+        }
+        let mut player = Player::new(id, name.clone());
+        player.host = !players.iter().any(|player| player.host);
+        players.push(player);
+        self.events
+            .push(EventMessage::broadcast(GameEvent::PlayerJoined { name }));
+        true
     }
 
     /// Removes a player from the game.
@@ -224,28 +334,66 @@ impl LobbyStateInner {
     ///  * `id` - The id of the player.
     ///
     pub fn remove_player(&mut self, player_id: i64) {
-        // leave ingame when player is drawer
-        let mut end_game = false;
+        let mut end_turn = false;
+        let mut end_match = false;
         {
             let game_state = self.game_state.lock().unwrap();
             let mut players = self.players.lock().unwrap();
+            // skip the rest of the turn when the drawer leaves
             if game_state.in_game {
                 if let Some(player) = players.iter_mut().find(|player| player.id == player_id) {
                     if player.drawing {
-                        end_game = true;
+                        end_turn = true;
                     }
                 }
             }
+            if let Some(player) = players.iter().find(|player| player.id == player_id) {
+                self.events
+                    .push(EventMessage::broadcast(GameEvent::PlayerLeft {
+                        name: player.name.clone(),
+                    }));
+            }
             players.retain(|player| player.id != player_id);
-            // leave ingame when only 1 player
-            if players.len() < MIN_NUMBER_PLAYERS {
-                end_game = true;
+            self.chat_moderator.forget(player_id);
+            // the longest waiting player takes over when the host leaves
+            if !players.iter().any(|player| player.host) {
+                if let Some(player) = players.first_mut() {
+                    player.host = true;
+                }
             }
+            // end the match when too few players are left to play it
+            if game_state.in_game
+                && players.iter().filter(|player| player.playing).count() < MIN_NUMBER_PLAYERS
+            {
+                end_match = true;
+            }
+        }
+
+        if end_match {
+            self.end_match();
+        } else if end_turn {
+            self.end_turn();
         }
+    }
+
+    /// Check if no player is in the lobby and no client is about to join it.
+    pub fn is_empty(&self) -> bool {
+        self.players.lock().unwrap().is_empty() && self.client_txs.is_empty()
+    }
 
-        if end_game {
-            self.end_game();
+    /// The languages of the word packs the lobby plays with, none if it only plays custom words.
+    pub fn languages(&self) -> Vec<String> {
+        let mut languages: Vec<String> = Vec::new();
+        if self.settings.only_custom_words {
+            return languages;
         }
+        for pack in self.word_packs.iter() {
+            if self.settings.word_packs.contains(&pack.name) && !languages.contains(&pack.language)
+            {
+                languages.push(pack.language.clone());
+            }
+        }
+        languages
     }
 
     /// Check if all players are ready.
@@ -257,6 +405,11 @@ impl LobbyStateInner {
             .all(|player| player.ready)
     }
 
+    /// Check if a match can start, all players are ready and there are enough of them.
+    pub fn can_start(&self) -> bool {
+        self.all_ready() && self.players.lock().unwrap().len() >= MIN_NUMBER_PLAYERS
+    }
+
     /// Set the ready status of a player.
     ///
     /// # Arguments
@@ -271,24 +424,242 @@ impl LobbyStateInner {
         }
     }
 
-    /// Check if all players have guessed the word.
+    /// Replaces the settings of the lobby.
+    ///
+    /// # Arguments
+    ///  * `player_id` - The id of the player changing the settings.
+    ///  * `settings` - The new settings.
+    ///
+    /// # Returns
+    ///  * `Ok(())` - The settings were changed.
+    ///  * `Err(error)` - The player is not the host, a game is running or the settings are invalid.
+    pub fn update_settings(
+        &mut self,
+        player_id: i64,
+        settings: LobbySettings,
+    ) -> Result<(), SettingsError> {
+        self.check_may_change_settings(player_id)?;
+        settings.validate()?;
+        if self.players.lock().unwrap().len() > settings.max_players {
+            return Err(SettingsError::TooManyPlayers);
+        }
+        if settings
+            .word_packs
+            .iter()
+            .any(|name| !self.word_packs.iter().any(|pack| &pack.name == name))
+        {
+            return Err(SettingsError::UnknownWordPack);
+        }
+        // the deck is only replaced when other words are chosen, so it keeps avoiding the recent words
+        if settings.word_packs != self.settings.word_packs
+            || settings.difficulties != self.settings.difficulties
+            || settings.only_custom_words != self.settings.only_custom_words
+        {
+            self.word_deck = WordDeck::new(self.lobby_words(&settings)?);
+        }
+        self.reward_strategy_guesser =
+            rewardstrategy::guesser_strategy(settings.guesser_scoring, settings.round_time);
+        self.reward_strategy_drawer =
+            rewardstrategy::drawer_strategy(settings.drawer_scoring, settings.round_time);
+        self.settings = settings;
+        Ok(())
+    }
+
+    /// Replaces the custom words of the lobby, they are played together with the words of the word packs
+    /// or on their own, as the settings say.
+    /// Removing all custom words goes back to playing only the words of the word packs.
+    ///
+    /// # Arguments
+    /// * `player_id` - The id of the player uploading the words, has to be the host.
+    /// * `words` - The words as typed by the host, an empty list removes the custom words.
+    ///
+    /// # Returns
+    /// * `Ok(count)` - The number of custom words the lobby plays with now.
+    /// * `Err(error)` - Why the words were refused, the previous custom words are kept.
+    pub fn set_custom_words(
+        &mut self,
+        player_id: i64,
+        words: &[String],
+    ) -> Result<usize, SettingsError> {
+        self.check_may_change_settings(player_id)?;
+        let words = settings_common::clean_custom_words(words)?;
+        let mut settings = self.settings.clone();
+        if words.is_empty() {
+            settings.only_custom_words = false;
+        }
+        let previous_words = std::mem::replace(&mut self.custom_words, words);
+        match self.lobby_words(&settings) {
+            Ok(entries) => {
+                self.word_deck = WordDeck::new(entries);
+                self.settings = settings;
+                Ok(self.custom_words.len())
+            }
+            Err(e) => {
+                self.custom_words = previous_words;
+                Err(e)
+            }
+        }
+    }
+
+    /// Checks that a player may change the settings of the lobby right now.
+    ///
+    /// # Arguments
+    /// * `player_id` - The id of the player changing the settings.
+    ///
+    /// # Returns
+    /// * `Ok(())` - The player is the host and no game is running.
+    /// * `Err(error)` - Why the settings cannot be changed.
+    fn check_may_change_settings(&self, player_id: i64) -> Result<(), SettingsError> {
+        if !self
+            .players
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|player| player.id == player_id && player.host)
+        {
+            return Err(SettingsError::NotHost);
+        }
+        if self.game_state.lock().unwrap().in_game {
+            return Err(SettingsError::InGame);
+        }
+        Ok(())
+    }
+
+    /// Collects the words the lobby plays with under the given settings.
+    ///
+    /// # Arguments
+    /// * `settings` - The settings choosing the word packs, difficulties and whether only custom words are played.
+    ///
+    /// # Returns
+    /// * `Ok(words)` - The words of the chosen word packs and the custom words of the lobby.
+    /// * `Err(error)` - There would be no words to play.
+    fn lobby_words(&self, settings: &LobbySettings) -> Result<Vec<WordEntry>, SettingsError> {
+        if settings.only_custom_words && self.custom_words.is_empty() {
+            return Err(SettingsError::NoCustomWords);
+        }
+        let mut words = if settings.only_custom_words {
+            Vec::new()
+        } else {
+            wordpack::select_words(
+                &self.word_packs,
+                &settings.word_packs,
+                &settings.difficulties,
+            )
+        };
+        // custom words are played whatever difficulties are chosen
+        words.extend(self.custom_words.iter().cloned().map(WordEntry::new));
+        if words.is_empty() {
+            return Err(SettingsError::NoWords);
+        }
+        Ok(words)
+    }
+
+    /// Check if all players have guessed the word, the turn ends if they have.
     ///
     /// # Returns
     ///  * `true` - If all players have guessed the word.
-    /// * `false` - If not all players have guessed the word.
+    /// * `false` - If not all players have guessed the word or no word is being drawn.
     pub fn all_guessed(&mut self) -> bool {
+        {
+            let game_state = self.game_state.lock().unwrap();
+            if !game_state.in_game || game_state.phase != GamePhase::Drawing {
+                return false;
+            }
+        }
         if self.players.lock().unwrap().iter().all(|player| {
             !player.playing
                 || !player.drawing && player.guessed_word
                 || player.drawing && !player.guessed_word
         }) {
-            self.end_game();
+            self.end_turn();
             return true;
         }
         false
     }
 
-    pub fn add_line_msg(&mut self, line_msg: Value) {
+    /// Get the role of a player, clients without a player are spectators.
+    ///
+    /// # Arguments
+    ///  * `player_id` - The id of the player.
+    pub fn role_of(&self, player_id: i64) -> PlayerRole {
+        self.players
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|player| player.id == player_id)
+            .map_or(PlayerRole::Spectator, |player| player.role())
+    }
+
+    /// Checks if a player may change the drawing right now.
+    /// Only the drawer may draw, and only while the word is being drawn.
+    ///
+    /// # Arguments
+    ///  * `player_id` - The id of the player.
+    pub fn may_draw(&self, player_id: i64) -> bool {
+        self.role_of(player_id) == PlayerRole::Drawer
+            && self.game_state.lock().unwrap().phase == GamePhase::Drawing
+    }
+
+    /// Creates the version of a broadcast message a single client may receive.
+    /// Game state updates are reduced to what the role of the client is allowed to see.
+    ///
+    /// # Arguments
+    ///  * `client_id` - The id of the receiving client.
+    ///  * `msg` - The message to be broadcast.
+    ///
+    /// # Returns
+    ///  * `Some(msg)` - The message the client receives.
+    ///  * `None` - The client may not read the message, like the chat of the players who know the word.
+    pub fn message_for(&self, client_id: i64, msg: &Message) -> Option<Message> {
+        match msg {
+            Message::GameStateUpdate(update) => Some(Message::GameStateUpdate(
+                GameStateUpdate::new(update.game_state.view_for(self.role_of(client_id))),
+            )),
+            Message::ChatMessage(chat)
+                if chat.channel == ChatChannel::Guessed
+                    && !self.role_of(client_id).knows_word() =>
+            {
+                None
+            }
+            Message::Event(event) => match event.audience {
+                Audience::Player(id) if id != client_id => None,
+                _ => Some(msg.clone()),
+            },
+            _ => Some(msg.clone()),
+        }
+    }
+
+    /// Checks a chat message before it is handled as chat or guess.
+    /// While the word is drawn the players who know it only reach each other in the guessed channel,
+    /// but the drawer choosing the word still chats with everybody and may not give away the words offered.
+    ///
+    /// # Arguments
+    /// * `player_id` - The id of the player sending the message.
+    /// * `message` - The message.
+    ///
+    /// # Returns
+    /// * `ChatVerdict` - `Allowed` if the message may be handled.
+    pub fn moderate_chat(&mut self, player_id: i64, message: &str) -> ChatVerdict {
+        let verdict = self.chat_moderator.check(player_id, message);
+        if verdict != ChatVerdict::Allowed {
+            return verdict;
+        }
+        if self.role_of(player_id) != PlayerRole::Drawer {
+            return ChatVerdict::Allowed;
+        }
+        let game_state = self.game_state.lock().unwrap();
+        if game_state.phase == GamePhase::ChoosingWord
+            && game_state
+                .word_choices
+                .iter()
+                .any(|word| moderation::leaks_word(message, word, self.word_deck.aliases(word)))
+        {
+            return ChatVerdict::LeaksWord;
+        }
+        ChatVerdict::Allowed
+    }
+
+    pub fn add_line_msg(&mut self, line_msg: Message) {
         self.lines.lock().unwrap().push(line_msg)
     }
 
@@ -302,88 +673,347 @@ impl LobbyStateInner {
         let game_state = self.game_state.lock().unwrap();
         let mut players = self.players.lock().unwrap();
         let nr_players_finished = players.iter().filter(|p| p.guessed_word).count();
+        let hints_revealed = game_state.hints_revealed();
+        let guess_match = guess::check_guess(
+            message,
+            &game_state.word,
+            self.word_deck.aliases(&game_state.word),
+            GuessRules {
+                accept_plurals: self.settings.accept_plurals,
+                accept_aliases: self.settings.accept_aliases,
+            },
+        );
         let number_of_guessers = players
             .iter()
             .filter(|player| !player.drawing && player.playing)
             .count();
         let mut result = GuessResult::Incorrect;
         for player in &mut players.iter_mut() {
-            if game_state.in_game && player.id == player_id {
+            // nothing can be guessed while the drawer is still choosing the word
+            if game_state.phase == GamePhase::Drawing && player.id == player_id {
                 if !player.playing {
                     result = GuessResult::Spectating;
                 } else if player.drawing {
                     result = GuessResult::Drawing;
                 } else if player.guessed_word {
                     result = GuessResult::AlreadyGuessed;
-                } else if game_state.word.to_lowercase().eq(&message.to_lowercase()) {
+                } else if guess_match == GuessMatch::Correct {
                     player.guessed_word = true;
+                    let score = player.score;
                     self.reward_strategy_guesser.reward_points_to_guesser(
                         player,
                         number_of_guessers,
                         nr_players_finished,
                         game_state.time,
+                        hints_revealed,
                     );
+                    self.turn_guesses.push(TurnScore {
+                        id: player.id,
+                        name: player.name.clone(),
+                        points: player.score - score,
+                    });
                     result = GuessResult::Correct;
-                } else if edit_distance(&*game_state.word.to_lowercase(), &message.to_lowercase())
-                    <= MAX_ALLOWED_EDIT_DISTANCE_FOR_ALMOST
-                {
+                } else if guess_match == GuessMatch::Almost {
                     result = GuessResult::Almost;
                 }
             }
         }
         if result == GuessResult::Correct {
             let drawer = players.iter_mut().find(|player| player.drawing).unwrap();
+            let score = drawer.score;
             self.reward_strategy_drawer.reward_points_to_drawer(
                 drawer,
                 number_of_guessers,
                 nr_players_finished,
                 game_state.time,
+                hints_revealed,
             );
+            self.drawer_points += drawer.score - score;
         }
         result
     }
 
-    /// Gets a new random word from the word list and removes it from the word list.
+    /// Draws words from the word deck and offers them to the drawer.
     ///
-    /// # Returns
-    /// * `word` - The new random word.
-    fn get_random_word(&mut self) {
+    /// # Arguments
+    /// * `count` - The number of words to offer.
+    fn offer_word_choices(&mut self, count: usize) {
+        self.return_word_choices();
+        self.game_state.lock().unwrap().word_choices = self.word_deck.draw(count);
+    }
+
+    /// Puts the words the drawer did not choose back into the word deck.
+    fn return_word_choices(&mut self) {
+        let choices = std::mem::take(&mut self.game_state.lock().unwrap().word_choices);
+        self.word_deck.put_back(choices);
+    }
+
+    /// Sets the word the drawer chose and starts drawing.
+    /// Choices from anybody but the drawer or outside of the choosing phase are ignored.
+    ///
+    /// # Arguments
+    /// * `player_id` - The id of the player choosing the word.
+    /// * `choice` - The index of the chosen word in the offered words.
+    pub fn choose_word(&mut self, player_id: i64, choice: usize) {
+        if self.role_of(player_id) != PlayerRole::Drawer {
+            return;
+        }
+        {
+            let mut game_state = self.game_state.lock().unwrap();
+            if game_state.phase != GamePhase::ChoosingWord
+                || choice >= game_state.word_choices.len()
+            {
+                return;
+            }
+            let word = game_state.word_choices.remove(choice);
+            self.word_deck.mark_used(&word);
+            game_state.word_length = word.chars().count() as i64;
+            game_state.hint = hidden_hint(&word);
+            game_state.word = word;
+            game_state.phase = GamePhase::Drawing;
+            game_state.time = self.settings.round_time;
+        }
+        self.return_word_choices();
+    }
+
+    /// Reveals letters of the word as the turn goes on, following the hint schedule of the lobby.
+    /// The revealed letters are part of the shared hint, so every guesser sees the same ones.
+    fn reveal_hints(&mut self) {
         let mut game_state = self.game_state.lock().unwrap();
-        let mut words = self.word_list.lock().unwrap();
-        let word_index = rand::thread_rng().gen_range(0, words.len());
-        game_state.word = words[word_index].clone();
-        game_state.word_length = words[word_index].len() as i64;
-        words.remove(word_index);
+        if game_state.phase != GamePhase::Drawing {
+            return;
+        }
+        let letters = game_state
+            .word
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .count();
+        let elapsed = self.settings.round_time - game_state.time;
+        let due = self
+            .settings
+            .hint_schedule
+            .hints_due(letters, elapsed, self.settings.round_time);
+        while game_state.hints_revealed() < due {
+            let hidden: Vec<usize> = (0..game_state.hint.len())
+                .filter(|i| game_state.hint[*i].is_none())
+                .collect();
+            if hidden.is_empty() {
+                return;
+            }
+            let position = hidden[rand::thread_rng().gen_range(0, hidden.len())];
+            game_state.hint[position] = game_state.word.chars().nth(position);
+        }
+    }
+
+    /// Moves the game on when the timer of the current phase runs out.
+    /// A drawer who did not choose a word in time gets a random one of the offered words,
+    /// after the summary of a turn the next turn starts, otherwise the turn is over.
+    pub fn time_up(&mut self) {
+        let (phase, count) = {
+            let game_state = self.game_state.lock().unwrap();
+            (game_state.phase, game_state.word_choices.len())
+        };
+        match phase {
+            GamePhase::ChoosingWord if count > 0 => {
+                let drawer = self
+                    .players
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .find(|player| player.drawing)
+                    .map(|player| player.id);
+                if let Some(drawer) = drawer {
+                    self.choose_word(drawer, rand::thread_rng().gen_range(0, count));
+                }
+            }
+            GamePhase::TurnOver => self.start_next_turn(),
+            _ => self.end_turn(),
+        }
+    }
+
+    /// Starts a new match.
+    /// The scores are reset, every player in the lobby takes part and the drawing order is shuffled once,
+    /// then everybody draws once per round in that order.
+    pub fn start_match(&mut self) {
+        {
+            let mut game_state = self.game_state.lock().unwrap();
+            let mut players = self.players.lock().unwrap();
+            println!("Starting match with {} rounds", self.settings.rounds);
+            for player in players.iter_mut() {
+                player.score = 0;
+                player.playing = true;
+                player.ready = false;
+            }
+            self.drawer_order = players.iter().map(|player| player.id).collect();
+            rand::thread_rng().shuffle(&mut self.drawer_order);
+            game_state.in_game = true;
+            game_state.phase = GamePhase::Drawing;
+            game_state.round = 1;
+            game_state.rounds = self.settings.rounds;
+            game_state.turn = 0;
+            game_state.turns = self.drawer_order.len();
+            game_state.final_scores.clear();
+            game_state.turn_summary = None;
+            self.events
+                .push(EventMessage::broadcast(GameEvent::RoundStarted {
+                    round: 1,
+                    rounds: game_state.rounds,
+                }));
+        }
+        self.start_next_turn();
+    }
+
+    /// Starts the turn of the next player in the drawing order who is still playing.
+    /// Moves on to the next round after the last player of a round, the match ends after the last round.
+    fn start_next_turn(&mut self) {
+        loop {
+            let drawer_id = {
+                let mut game_state = self.game_state.lock().unwrap();
+                if game_state.turn == self.drawer_order.len() {
+                    if game_state.round == game_state.rounds {
+                        None
+                    } else {
+                        self.events
+                            .push(EventMessage::broadcast(GameEvent::RoundEnded {
+                                round: game_state.round,
+                            }));
+                        game_state.turn = 1;
+                        game_state.round += 1;
+                        self.events
+                            .push(EventMessage::broadcast(GameEvent::RoundStarted {
+                                round: game_state.round,
+                                rounds: game_state.rounds,
+                            }));
+                        Some(self.drawer_order[0])
+                    }
+                } else {
+                    game_state.turn += 1;
+                    Some(self.drawer_order[game_state.turn - 1])
+                }
+            };
+            let drawer_id = match drawer_id {
+                Some(drawer_id) => drawer_id,
+                None => return self.end_match(),
+            };
+            // players who left keep their place in the order, their turns are skipped
+            let drawer_present = self
+                .players
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|player| player.id == drawer_id && player.playing);
+            if drawer_present {
+                return self.start_turn(drawer_id);
+            }
+        }
     }
 
-    /// Starts a new game.
-    pub fn start_game(&mut self) {
-        println!("Starting Game");
-        self.get_random_word();
+    /// Starts a turn by offering the drawer words to choose from.
+    ///
+    /// # Arguments
+    /// * `drawer_id` - The id of the player drawing in this turn.
+    fn start_turn(&mut self, drawer_id: i64) {
+        self.offer_word_choices(self.settings.word_choices);
         let mut game_state = self.game_state.lock().unwrap();
         let mut players = self.players.lock().unwrap();
-        game_state.in_game = true;
-        game_state.time = GAME_TIME;
-        let drawer_index = rand::thread_rng().gen_range(0, players.len());
-        for (index, player) in (&mut players.iter_mut()).enumerate() {
-            if drawer_index == index {
-                player.drawing = true;
-            } else {
-                player.drawing = false;
-            }
+        println!(
+            "Starting turn {} of round {}",
+            game_state.turn, game_state.round
+        );
+        game_state.phase = GamePhase::ChoosingWord;
+        game_state.turn_summary = None;
+        game_state.word = "".to_string();
+        game_state.word_length = 0;
+        game_state.hint.clear();
+        game_state.time = WORD_CHOICE_TIME;
+        for player in players.iter_mut() {
+            player.drawing = player.id == drawer_id;
             player.guessed_word = false;
-            player.playing = true;
-            player.ready = false;
+        }
+        self.turn_guesses.clear();
+        self.drawer_points = 0;
+        self.lines.lock().unwrap().clear();
+    }
+
+    /// Tells everybody the word of the turn that is ending, if it was being drawn.
+    fn reveal_word(&mut self) {
+        let game_state = self.game_state.lock().unwrap();
+        if game_state.phase == GamePhase::Drawing && !game_state.word.is_empty() {
+            self.events
+                .push(EventMessage::broadcast(GameEvent::WordRevealed {
+                    word: game_state.word.clone(),
+                }));
         }
     }
 
-    /// Ends the game.
-    fn end_game(&mut self) {
+    /// Ends the current turn.
+    /// A turn the word was drawn in is summed up first, the timer starts the next turn once the summary was shown.
+    fn end_turn(&mut self) {
+        self.reveal_word();
+        self.return_word_choices();
+        let (in_game, phase) = {
+            let game_state = self.game_state.lock().unwrap();
+            (game_state.in_game, game_state.phase)
+        };
+        match phase {
+            GamePhase::Drawing => self.show_turn_summary(),
+            // the summary is shown until its time is up, even if the drawer left
+            GamePhase::TurnOver => {}
+            _ if in_game => self.start_next_turn(),
+            _ => {}
+        }
+    }
+
+    /// Shows everybody the word of the turn, who guessed it in which order and the points everybody earned.
+    fn show_turn_summary(&mut self) {
         let mut game_state = self.game_state.lock().unwrap();
+        let drawer = self
+            .players
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|player| player.drawing)
+            .map(|player| TurnScore {
+                id: player.id,
+                name: player.name.clone(),
+                points: self.drawer_points,
+            });
+        game_state.turn_summary = Some(TurnSummary {
+            guesses: std::mem::take(&mut self.turn_guesses),
+            drawer,
+        });
+        game_state.hint = game_state.word.chars().map(Some).collect();
+        game_state.phase = GamePhase::TurnOver;
+        game_state.time = TURN_SUMMARY_TIME;
+    }
+
+    /// Ends the match, the final scores are kept until the next match starts.
+    fn end_match(&mut self) {
+        self.reveal_word();
+        self.return_word_choices();
+        let mut game_state = self.game_state.lock().unwrap();
+        self.events
+            .push(EventMessage::broadcast(GameEvent::RoundEnded {
+                round: game_state.round,
+            }));
         let mut players = self.players.lock().unwrap();
+        let mut final_scores: Vec<FinalScore> = players
+            .iter()
+            .filter(|player| player.playing)
+            .map(|player| FinalScore {
+                id: player.id,
+                name: player.name.clone(),
+                score: player.score,
+            })
+            .collect();
+        final_scores.sort_by_key(|score| std::cmp::Reverse(score.score));
+        game_state.final_scores = final_scores;
+        game_state.turn_summary = None;
         game_state.in_game = false;
+        game_state.phase = GamePhase::MatchOver;
         game_state.word = "".to_string();
         game_state.word_length = 0;
+        game_state.hint.clear();
         game_state.time = 0;
         for player in &mut players.iter_mut() {
             player.guessed_word = false;
@@ -391,6 +1021,7 @@ impl LobbyStateInner {
             player.ready = false;
             player.drawing = false;
         }
+        self.drawer_order.clear();
         self.lines.lock().unwrap().clear();
     }
 }
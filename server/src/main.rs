@@ -1,53 +1,90 @@
 #![crate_name = "rust_scribble_server"]
 
-use std::{
-    fs::File,
-    io::{BufRead, BufReader},
-    net::Ipv4Addr,
-    path::Path,
-};
+use std::{net::Ipv4Addr, process, time::Duration};
 
 use clap::Parser;
+use rust_scribble_common::handshake_common::EncryptionMode;
+use rust_scribble_common::network_common::DEFAULT_MAX_FRAME_SIZE;
 
 use crate::lobbystate::LobbyState;
-use crate::network::handle_client;
+use crate::network::{handle_client, ConnectionSettings};
 use crate::scribblserver::ScribblServer;
 
+mod guess;
+mod identity;
+mod lobbyregistry;
 mod lobbystate;
+mod moderation;
 mod network;
 mod rewardstrategy;
 mod scribblserver;
+mod worddeck;
+mod wordpack;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
     #[clap(short, long, value_parser, default_value_t = 3000)]
     port: u16,
-    #[clap(short, long, value_parser, default_value = "assets/words.txt")]
-    words: String,
+    /// Directory holding the word packs as JSON files.
+    #[clap(short, long, value_parser, default_value = "assets/packs")]
+    word_packs: String,
+    /// Language of the word packs new lobbies start with.
+    #[clap(short, long, value_parser, default_value = "English")]
+    language: String,
+    /// File holding the long-term identity key clients use to authenticate the server.
+    /// A new key is created if the file does not exist.
+    #[clap(short, long, value_parser, default_value = "server_identity.key")]
+    identity: String,
+    /// Largest message in bytes a client may send, larger frames close the connection.
+    #[clap(long, value_parser, default_value_t = DEFAULT_MAX_FRAME_SIZE)]
+    max_frame_size: u32,
+    /// Seconds a lobby may stay without players before it is closed.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(10..), default_value_t = 60)]
+    lobby_idle_timeout: u64,
+    /// Also accept clients that ask for an unencrypted connection.
+    #[clap(long, value_parser)]
+    allow_plaintext: bool,
 }
 
 /// Main function for setting up and running a scribbl server.
-fn main() {
+#[tokio::main]
+async fn main() {
     let args = Args::parse();
 
-    let words = read_words_from_file(args.words);
+    let word_packs = match wordpack::load_word_packs(&args.word_packs) {
+        Ok(word_packs) => word_packs,
+        Err(e) => {
+            println!(
+                "Could not read the word packs in {}: {}",
+                args.word_packs, e
+            );
+            process::exit(1);
+        }
+    };
+    if word_packs.is_empty() {
+        println!("There are no word packs with words in {}", args.word_packs);
+        process::exit(1);
+    }
+    let identity =
+        identity::load_or_create_identity(args.identity).expect("Could not load server identity");
+    let mut encryption_modes = EncryptionMode::default_modes();
+    if args.allow_plaintext && !encryption_modes.contains(&EncryptionMode::Plaintext) {
+        encryption_modes.push(EncryptionMode::Plaintext);
+    }
+    let connection_settings = ConnectionSettings {
+        identity,
+        max_frame_size: args.max_frame_size,
+        encryption_modes,
+    };
     let loopback = Ipv4Addr::new(0, 0, 0, 0);
-    let server = ScribblServer::init(loopback, args.port, words);
-    server.run()
-}
-
-/// Get Words from File and put them in a vector
-///
-/// # Arguments
-/// * `filename` - The path to the file containing the words.
-///
-/// # Returns
-/// * Vec<String> - A vector of strings containing the words.
-fn read_words_from_file(filename: impl AsRef<Path>) -> Vec<String> {
-    let file = File::open(filename).expect("no such file");
-    let buf = BufReader::new(file);
-    buf.lines()
-        .map(|l| l.expect("Could not parse line"))
-        .collect()
+    let server = ScribblServer::init(
+        loopback,
+        args.port,
+        word_packs,
+        &args.language,
+        Duration::from_secs(args.lobby_idle_timeout),
+        connection_settings,
+    );
+    server.run().await
 }
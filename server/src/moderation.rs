@@ -0,0 +1,262 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use rust_scribble_common::messages_common::MAX_CHAT_MESSAGE_LENGTH;
+
+use crate::guess::{self, GuessMatch, GuessRules};
+
+/// Number of chat messages a player may send within `CHAT_RATE_WINDOW`.
+const MAX_CHAT_MESSAGES: usize = 5;
+/// The time span the chat messages of a player are counted in.
+const CHAT_RATE_WINDOW: Duration = Duration::from_secs(5);
+/// The fewest letters a part of the word needs to count as giving the word away.
+const MIN_LEAK_LETTERS: usize = 4;
+
+/// What happens to a chat message.
+#[derive(Eq, PartialEq, Debug)]
+pub(crate) enum ChatVerdict {
+    /// The message is passed on.
+    Allowed,
+    /// The message is longer than `MAX_CHAT_MESSAGE_LENGTH`.
+    TooLong,
+    /// The player sent too many messages recently, only the first dropped message is reported.
+    TooFast { report: bool },
+    /// The message would give away a word to players who have to guess it.
+    LeaksWord,
+}
+
+/// Keeps players from flooding the chat of a lobby.
+#[derive(Default)]
+pub(crate) struct ChatModerator {
+    /// When the recent messages of every player were sent, the oldest at the front.
+    sent: HashMap<i64, VecDeque<Instant>>,
+    /// The players whose dropped messages were already reported.
+    reported: HashSet<i64>,
+}
+
+impl ChatModerator {
+    /// Checks the length of a chat message and how many messages the player sent recently.
+    ///
+    /// # Arguments
+    /// * `player_id` - The id of the player sending the message.
+    /// * `message` - The message.
+    ///
+    /// # Returns
+    /// * `ChatVerdict` - `Allowed` if the message may be passed on.
+    pub fn check(&mut self, player_id: i64, message: &str) -> ChatVerdict {
+        self.check_at(player_id, message, Instant::now())
+    }
+
+    /// Checks a chat message sent at the given time.
+    ///
+    /// # Arguments
+    /// * `player_id` - The id of the player sending the message.
+    /// * `message` - The message.
+    /// * `now` - When the message was sent.
+    fn check_at(&mut self, player_id: i64, message: &str, now: Instant) -> ChatVerdict {
+        if message.chars().count() > MAX_CHAT_MESSAGE_LENGTH {
+            return ChatVerdict::TooLong;
+        }
+        let sent = self.sent.entry(player_id).or_default();
+        while let Some(time) = sent.front() {
+            if now.duration_since(*time) < CHAT_RATE_WINDOW {
+                break;
+            }
+            sent.pop_front();
+        }
+        if sent.len() >= MAX_CHAT_MESSAGES {
+            return ChatVerdict::TooFast {
+                report: self.reported.insert(player_id),
+            };
+        }
+        sent.push_back(now);
+        self.reported.remove(&player_id);
+        ChatVerdict::Allowed
+    }
+
+    /// Forgets a player who left the lobby.
+    ///
+    /// # Arguments
+    /// * `player_id` - The id of the player.
+    pub fn forget(&mut self, player_id: i64) {
+        self.sent.remove(&player_id);
+        self.reported.remove(&player_id);
+    }
+}
+
+/// Checks if a message gives away the word.
+/// A message does if it has the word in it, or a part of it, even spelled out like "p a l m",
+/// or something that would count as a correct or almost correct guess.
+///
+/// # Arguments
+/// * `message` - The chat message.
+/// * `word` - The word the players guessing may not learn.
+/// * `aliases` - The other spellings of the word.
+pub(crate) fn leaks_word(message: &str, word: &str, aliases: &[String]) -> bool {
+    let answers: Vec<String> = std::iter::once(word)
+        .chain(aliases.iter().map(String::as_str))
+        .map(guess::normalize)
+        .filter(|answer| !answer.is_empty())
+        .collect();
+    let whole = guess::normalize(message);
+    if answers.iter().any(|answer| whole.contains(answer.as_str())) {
+        return true;
+    }
+    let longest = answers
+        .iter()
+        .map(|answer| answer.chars().count())
+        .max()
+        .unwrap_or(0);
+    let rules = GuessRules {
+        accept_plurals: true,
+        accept_aliases: true,
+    };
+    let words: Vec<String> = message
+        .split_whitespace()
+        .map(guess::normalize)
+        .filter(|part| !part.is_empty())
+        .collect();
+    // every run of consecutive words, so letters spread over several words are caught as well
+    for start in 0..words.len() {
+        let mut run = String::new();
+        for part in &words[start..] {
+            run.push_str(part);
+            let letters = run.chars().count();
+            // longer runs are neither part of the word nor almost the word
+            if letters > longest + guess::MAX_TYPOS {
+                break;
+            }
+            if letters >= MIN_LEAK_LETTERS
+                && (answers.iter().any(|answer| answer.contains(run.as_str()))
+                    || guess::check_guess(&run, word, aliases, rules) != GuessMatch::Wrong)
+            {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaks(message: &str, word: &str) -> bool {
+        leaks_word(message, word, &[])
+    }
+
+    #[test]
+    fn the_word_itself_leaks() {
+        assert!(leaks("it is a palm tree", "palm tree"));
+        assert!(leaks("PALM-TREE!", "palm tree"));
+        assert!(leaks("nice palmtrees there", "palm tree"));
+        assert!(leaks("das ist ein eichhornchen", "Eichhörnchen"));
+    }
+
+    #[test]
+    fn spelled_out_words_leak() {
+        assert!(leaks("p a l m", "palm tree"));
+        assert!(leaks("haha s u n f", "sunflowers"));
+        assert!(leaks("t r e e", "palm tree"));
+    }
+
+    #[test]
+    fn parts_of_the_word_leak() {
+        assert!(leaks("it starts with sunf", "sunflowers"));
+        assert!(leaks("think of flower", "sunflowers"));
+    }
+
+    #[test]
+    fn near_misses_leak() {
+        assert!(leaks("palm treez", "palm tree"));
+        assert!(leaks("its an elefant", "elephant"));
+        assert!(leaks("sunflower", "sunflowers"));
+    }
+
+    #[test]
+    fn aliases_leak() {
+        let aliases = ["muffin".to_string()];
+        assert!(leaks_word("m u f f i n", "cupcake", &aliases));
+        assert!(!leaks_word("m u f f i n", "cupcake", &[]));
+    }
+
+    #[test]
+    fn harmless_messages_pass() {
+        assert!(!leaks("nice drawing", "palm tree"));
+        assert!(!leaks("that was great", "cat"));
+        assert!(!leaks("so close", "cat"));
+        assert!(!leaks("good one, well done", "elephant"));
+        assert!(!leaks("haha", "sunflowers"));
+        assert!(!leaks("", "cat"));
+    }
+
+    #[test]
+    fn short_words_only_leak_whole() {
+        // a part of fewer than four letters gives nothing away
+        assert!(!leaks("sun is out", "sunflowers"));
+        assert!(!leaks("p a l", "palm tree"));
+    }
+
+    #[test]
+    fn long_messages_are_refused() {
+        let mut moderator = ChatModerator::default();
+        let longest = "a".repeat(MAX_CHAT_MESSAGE_LENGTH);
+        assert_eq!(moderator.check(1, &longest), ChatVerdict::Allowed);
+        assert_eq!(moderator.check(1, &(longest + "a")), ChatVerdict::TooLong);
+    }
+
+    #[test]
+    fn flooding_is_reported_once() {
+        let mut moderator = ChatModerator::default();
+        let start = Instant::now();
+        for _ in 0..MAX_CHAT_MESSAGES {
+            assert_eq!(moderator.check_at(1, "hi", start), ChatVerdict::Allowed);
+        }
+        assert_eq!(
+            moderator.check_at(1, "hi", start),
+            ChatVerdict::TooFast { report: true }
+        );
+        assert_eq!(
+            moderator.check_at(1, "hi", start),
+            ChatVerdict::TooFast { report: false }
+        );
+        // other players are not affected
+        assert_eq!(moderator.check_at(2, "hi", start), ChatVerdict::Allowed);
+    }
+
+    #[test]
+    fn messages_leave_the_window() {
+        let mut moderator = ChatModerator::default();
+        let start = Instant::now();
+        for _ in 0..MAX_CHAT_MESSAGES {
+            moderator.check_at(1, "hi", start);
+        }
+        assert_eq!(
+            moderator.check_at(1, "hi", start + CHAT_RATE_WINDOW / 2),
+            ChatVerdict::TooFast { report: true }
+        );
+        assert_eq!(
+            moderator.check_at(1, "hi", start + CHAT_RATE_WINDOW),
+            ChatVerdict::Allowed
+        );
+        // the next flood is reported again
+        for _ in 1..MAX_CHAT_MESSAGES {
+            moderator.check_at(1, "hi", start + CHAT_RATE_WINDOW);
+        }
+        assert_eq!(
+            moderator.check_at(1, "hi", start + CHAT_RATE_WINDOW),
+            ChatVerdict::TooFast { report: true }
+        );
+    }
+
+    #[test]
+    fn forgotten_players_start_over() {
+        let mut moderator = ChatModerator::default();
+        let start = Instant::now();
+        for _ in 0..=MAX_CHAT_MESSAGES {
+            moderator.check_at(1, "hi", start);
+        }
+        moderator.forget(1);
+        assert_eq!(moderator.check_at(1, "hi", start), ChatVerdict::Allowed);
+    }
+}
@@ -1,251 +1,640 @@
-use std::io::{BufRead, BufReader, Read};
-use std::sync::{mpsc, Arc, Mutex};
-use std::time::{Duration, Instant};
+use std::io::{Error, ErrorKind};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use chacha20poly1305::Key;
+use ed25519_dalek::{Keypair, Signer};
+use rust_scribble_common::handshake_common::{
+    negotiate, ClientHello, ClientKeyExchange, EncryptionMode, HandshakeFinished, LobbyRequest,
+    RejectReason, ServerHello,
+};
 use rust_scribble_common::messages_common::{
-    ChatMessage, DisconnectMessage, GameStateUpdate, PlayersUpdate,
+    ChatChannel, ChatMessage, DisconnectMessage, EventMessage, GameEvent, GameStateUpdate,
+    LobbySettingsUpdate, Message, PlayersUpdate, UserInit,
 };
 use rust_scribble_common::network_common::*;
-use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::time;
 use x25519_dalek::PublicKey;
 
+use crate::lobbyregistry::LobbyRegistry;
 use crate::lobbystate;
 use crate::lobbystate::{GuessResult, LobbyState};
+use crate::moderation::ChatVerdict;
 
 const DELAY_BEFORE_GAME_START: u64 = 3;
 // seconds
 const MIN_TIME_BETWEEN_PINGS: u64 = 15; // seconds
+const HANDSHAKE_TIMEOUT: u64 = 5; // seconds
+/// Number of messages queued for a client, a client that falls further behind is disconnected.
+const OUTBOUND_QUEUE_SIZE: usize = 1024;
+/// Number of messages queued for a lobby, senders wait while the queue is full.
+pub(crate) const LOBBY_QUEUE_SIZE: usize = 256;
+
+/// Settings shared by all client connections of a server.
+pub(crate) struct ConnectionSettings {
+    /// The long-term identity key the handshakes are signed with.
+    pub identity: Keypair,
+    /// The maximum size of a frame received from a client.
+    pub max_frame_size: u32,
+    /// The encryption modes clients may choose from.
+    pub encryption_modes: Vec<EncryptionMode>,
+}
 
 /// Handles a client message.
 ///
 /// # Arguments
-/// * `msg` - The message to be handled in JSON format.
+/// * `msg` - The message to be handled.
 /// * `lobby` - The lobby state which can be updated if necessary when processing the message.
 ///
 /// # Returns
-/// * `Vector<Value>` - A vector of JSON value messages that shall be sent to all clients of the lobby.
+/// * `Vector<Message>` - A vector of messages that shall be sent to all clients of the lobby.
 ///
-fn handle_message(msg: Value, lobby: &mut LobbyState) -> Vec<Value> {
-    let mut msg_to_send: Vec<Value> = vec![];
+fn handle_message(msg: Message, lobby: &mut LobbyState) -> Vec<Message> {
+    let mut msg_to_send: Vec<Message> = vec![];
 
     // kind 'update_requested' will automatically trigger send_update = true
     // and does not need to be handled explicitly
-    let send_update = !msg["kind"].eq("update");
-    let mut clean_up_lobby = false;
-
-    if msg["kind"].eq("user_init") {
-        let id = msg["id"].as_i64().unwrap();
-        let name = msg["username"].as_str().unwrap();
-        lobby.add_player(id, name.to_string());
-        msg_to_send.push(json!(PlayersUpdate::new(
-            lobby.players().lock().unwrap().to_vec()
-        )));
-    } else if msg["kind"].eq("ready") {
-        let id = msg["id"].as_i64().unwrap();
-        let status = msg["ready"].as_bool().unwrap();
-        lobby.set_ready(id, status);
-        if lobby.all_ready()
-            && lobby.players().lock().unwrap().len() >= lobbystate::MIN_NUMBER_PLAYERS
-        {
-            lobby.start_game_on_timer(DELAY_BEFORE_GAME_START);
+    let send_update = !matches!(msg, Message::GameStateUpdate(_));
+
+    match msg {
+        Message::UserInit(user_init) => {
+            // clients that finished their handshakes at the same time may have filled the lobby
+            if !lobby.add_player(user_init.id, user_init.username) {
+                println!("Lobby is full, disconnecting client {}", user_init.id);
+                if let Some(client_tx) = lobby.client_tx().get(&user_init.id) {
+                    let _ =
+                        client_tx.try_send(notice(user_init.id, "The lobby is full!".to_string()));
+                }
+                // the connection closes once the notice is sent
+                lobby.remove_client_tx(user_init.id);
+                return msg_to_send;
+            }
+            msg_to_send.push(Message::PlayersUpdate(PlayersUpdate::new(
+                lobby.players().lock().unwrap().to_vec(),
+            )));
+            msg_to_send.push(settings_update(lobby));
         }
-        msg_to_send.push(json!(PlayersUpdate::new(
-            lobby.players().lock().unwrap().to_vec()
-        )));
-    } else if msg["kind"].eq("chat_message") {
-        let guess_result = lobby.chat_or_correct_guess(
-            msg["id"].as_i64().unwrap(),
-            msg["message"].as_str().unwrap(),
-        );
-        match guess_result {
-            GuessResult::Correct => {
-                if lobby.all_guessed() {
-                    clean_up_lobby = true;
+        Message::Ready(ready) => {
+            lobby.set_ready(ready.id, ready.ready);
+            if lobby.can_start() {
+                lobby.start_game_on_timer(DELAY_BEFORE_GAME_START);
+            }
+            msg_to_send.push(Message::PlayersUpdate(PlayersUpdate::new(
+                lobby.players().lock().unwrap().to_vec(),
+            )));
+        }
+        Message::ChatMessage(chat) => {
+            match lobby.moderate_chat(chat.id, &chat.message) {
+                ChatVerdict::Allowed => {}
+                ChatVerdict::TooLong => {
+                    msg_to_send.push(notice(chat.id, "Message too long!".to_string()));
+                    return msg_to_send;
+                }
+                ChatVerdict::TooFast { report } => {
+                    if report {
+                        msg_to_send.push(notice(
+                            chat.id,
+                            "Slow down, messages are sent too fast!".to_string(),
+                        ));
+                    }
+                    return msg_to_send;
+                }
+                ChatVerdict::LeaksWord => {
+                    msg_to_send.push(notice(
+                        chat.id,
+                        "Message blocked, it gives away the word!".to_string(),
+                    ));
+                    return msg_to_send;
+                }
+            }
+            match lobby.chat_or_correct_guess(chat.id, &chat.message) {
+                GuessResult::Correct => {
+                    let name = lobby
+                        .players()
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .find(|player| player.id == chat.id)
+                        .map_or_else(String::new, |player| player.name.clone());
+                    msg_to_send.push(Message::Event(EventMessage::broadcast(
+                        GameEvent::CorrectGuess { id: chat.id, name },
+                    )));
+                    // the next turn starts as soon as everybody guessed the word
+                    lobby.all_guessed();
+                }
+                GuessResult::Incorrect => msg_to_send.push(Message::ChatMessage(ChatMessage::new(
+                    chat.id,
+                    chat.message.clone(),
+                ))),
+                // the drawer and the players who guessed the word only talk among themselves until the turn ends
+                GuessResult::AlreadyGuessed | GuessResult::Drawing => {
+                    msg_to_send.push(Message::ChatMessage(ChatMessage::in_channel(
+                        chat.id,
+                        chat.message.clone(),
+                        ChatChannel::Guessed,
+                    )))
+                }
+                // only the guesser learns how close the guess was
+                GuessResult::Almost => msg_to_send.push(Message::Event(EventMessage::private(
+                    chat.id,
+                    GameEvent::CloseGuess {
+                        guess: chat.message.clone(),
+                    },
+                ))),
+                GuessResult::Spectating => {
+                    msg_to_send.push(notice(chat.id, "Spectators may not chat!".to_string()))
+                }
+            }
+        }
+        Message::Disconnect(disconnect) => {
+            lobby.remove_client_tx(disconnect.id);
+            lobby.all_guessed();
+            msg_to_send.push(Message::PlayersUpdate(PlayersUpdate::new(
+                lobby.players().lock().unwrap().to_vec(),
+            )));
+        }
+        Message::LobbySettingsUpdate(update) => {
+            match lobby.update_settings(update.id, update.settings) {
+                Ok(()) => msg_to_send.push(settings_update(lobby)),
+                Err(e) => {
+                    msg_to_send.push(notice(update.id, format!("Settings not changed: {}", e)))
                 }
-                msg_to_send.push(json!(ChatMessage::new(
-                    msg["id"].as_i64().unwrap(),
-                    "Guessed the word correctly!".to_string()
-                )));
             }
-            GuessResult::Incorrect => msg_to_send.push(msg),
-            GuessResult::AlreadyGuessed => msg_to_send.push(json!(ChatMessage::new(
-                msg["id"].as_i64().unwrap(),
-                "Already guessed correctly!".to_string()
-            ))),
-            GuessResult::Almost => msg_to_send.push(json!(ChatMessage::new(
-                msg["id"].as_i64().unwrap(),
-                "Close!".to_string()
-            ))),
-            GuessResult::Drawing => msg_to_send.push(json!(ChatMessage::new(
-                msg["id"].as_i64().unwrap(),
-                "Drawer may not chat!".to_string()
-            ))),
-            GuessResult::Spectating => msg_to_send.push(json!(ChatMessage::new(
-                msg["id"].as_i64().unwrap(),
-                "Spectators may not chat!".to_string()
-            ))),
         }
-    } else if msg["kind"].eq("disconnect") {
-        let id = msg["id"].as_i64().unwrap();
-        lobby.remove_player(id);
-        if !lobby.game_state().lock().unwrap().in_game {
-            clean_up_lobby = true;
+        Message::CustomWords(custom_words) => {
+            match lobby.set_custom_words(custom_words.id, &custom_words.words) {
+                Ok(count) => {
+                    msg_to_send.push(settings_update(lobby));
+                    msg_to_send.push(Message::Event(EventMessage::broadcast(GameEvent::Notice {
+                        text: format!("Custom words changed: {} words", count),
+                    })));
+                }
+                Err(e) => msg_to_send.push(notice(
+                    custom_words.id,
+                    format!("Custom words not changed: {}", e),
+                )),
+            }
         }
-        if lobby.all_guessed() {
-            // cannot join ifs due to lock acquiring
-            clean_up_lobby = true;
+        Message::ChooseWord(choice) => lobby.choose_word(choice.id, choice.choice),
+        // the timer already moved the game on
+        Message::TimeUp => {}
+        Message::AddLine(painting_update) => {
+            if lobby.may_draw(painting_update.id) {
+                let msg = Message::AddLine(painting_update);
+                lobby.add_line_msg(msg.clone());
+                msg_to_send.push(msg);
+            }
         }
-        msg_to_send.push(json!(PlayersUpdate::new(
-            lobby.players().lock().unwrap().to_vec()
-        )));
-    } else if msg["kind"].eq("time_up") {
-        clean_up_lobby = true;
-    } else if msg["kind"].eq("add_line") {
-        lobby.add_line_msg(msg.clone());
-        msg_to_send.push(msg);
-    } else {
-        msg_to_send.push(msg);
+        Message::ClearAllLines(ref clear) | Message::ClearLastLine(ref clear) => {
+            if lobby.may_draw(clear.id) {
+                msg_to_send.push(msg);
+            }
+        }
+        msg @ Message::GameStateUpdate(_) => msg_to_send.push(msg),
+        Message::UpdateRequested => {}
+        // only ever sent from the server to clients
+        Message::PlayersUpdate(_) | Message::Event(_) | Message::Ping => {}
     }
 
-    if clean_up_lobby {
+    // joins, leaves and the progress of the match, also when the timer moved it on
+    msg_to_send.extend(lobby.take_events().into_iter().map(Message::Event));
+
+    if !lobby.game_state().lock().unwrap().in_game {
         //needed to allow timer to start game again
         lobby.cleanup_lobby_after_end_game();
     }
 
     if send_update {
-        msg_to_send.push(json!(GameStateUpdate::new(
-            lobby.game_state().lock().unwrap().clone()
+        msg_to_send.push(Message::GameStateUpdate(GameStateUpdate::new(
+            lobby.game_state().lock().unwrap().clone(),
         )));
-        msg_to_send.push(json!(PlayersUpdate::new(
-            lobby.players().lock().unwrap().to_vec()
+        msg_to_send.push(Message::PlayersUpdate(PlayersUpdate::new(
+            lobby.players().lock().unwrap().to_vec(),
         )));
     }
 
     msg_to_send
 }
 
-/// Loop listening for waiting on MPSC channel and handle sending broadcast messages for a single lobby.
-/// This function will run in a separate thread.
+/// Creates the message announcing the current settings of a lobby.
+///
+/// # Arguments
+/// * `lobby` - The lobby whose settings are announced.
+///
+/// # Returns
+/// * `Message` - The settings update sent on behalf of the host.
+///
+fn settings_update(lobby: &LobbyState) -> Message {
+    let host = lobby
+        .players()
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|player| player.host)
+        .map_or(0, |player| player.id);
+    Message::LobbySettingsUpdate(LobbySettingsUpdate::new(host, lobby.settings()))
+}
+
+/// Creates a notice of the server only one player receives.
+///
+/// # Arguments
+/// * `id` - The id of the player receiving the notice.
+/// * `text` - The notice.
+///
+/// # Returns
+/// * `Message` - The event carrying the notice.
+///
+fn notice(id: i64, text: String) -> Message {
+    Message::Event(EventMessage::private(id, GameEvent::Notice { text }))
+}
+
+/// The actor of a single lobby: handles the messages sent to the lobby
+/// and broadcasts the resulting messages to the queues of its clients.
+/// This function runs in its own task until the lobby is closed.
 ///
 /// # Arguments
 /// * `lobby` - The lobby which will process any actions and messages received.
-/// * `lobby_rx` - The channel to receive broadcast messages regarding the specified lobby from.
+/// * `lobby_rx` - The channel to receive the messages regarding the specified lobby from.
 ///
-pub(crate) fn check_send_broadcast_messages(
+pub(crate) async fn check_send_broadcast_messages(
     lobby: Arc<Mutex<LobbyState>>,
-    lobby_rx: mpsc::Receiver<Value>,
+    mut lobby_rx: mpsc::Receiver<Message>,
 ) {
+    let mut shutdown = lobby.lock().unwrap().shutdown_signal();
     loop {
-        if let Ok(msg) = lobby_rx.recv() {
-            if !msg["kind"].eq("update") {
-                println!("Received message: {:?}", msg);
-            }
-            let msgs_to_send = handle_message(msg, &mut lobby.lock().unwrap());
+        let msg = tokio::select! {
+            msg = lobby_rx.recv() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            _ = lobbystate::closed(&mut shutdown) => break,
+        };
+        if !matches!(msg, Message::GameStateUpdate(_)) {
+            println!(
+                "Received message: {}",
+                serde_json::to_string(&msg).unwrap_or_default()
+            );
+        }
+        let mut lobby = lobby.lock().unwrap();
+        let msgs_to_send = handle_message(msg, &mut lobby);
 
-            for msg in msgs_to_send.iter() {
-                let client_txs = lobby.lock().unwrap().client_tx();
-                for (client_id, client_tx) in client_txs.iter() {
-                    if client_tx.send(msg.clone()).is_err() {
-                        lobby.lock().unwrap().remove_client_tx(*client_id);
+        for msg in msgs_to_send.iter() {
+            for (client_id, client_tx) in lobby.client_tx().iter() {
+                // every client gets its own copy, redacted to what its player may see
+                let msg = match lobby.message_for(*client_id, msg) {
+                    Some(msg) => msg,
+                    None => continue,
+                };
+                match client_tx.try_send(msg) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => {
+                        println!("Client {} is not keeping up, disconnecting", client_id);
+                        lobby.remove_client_tx(*client_id);
                     }
+                    Err(TrySendError::Closed(_)) => lobby.remove_client_tx(*client_id),
                 }
             }
         }
     }
 }
 
-/// Send a JSON message to check if the client is still connected.
+/// Reads a single frame.
 ///
 /// # Arguments
-/// * `net_info` - The network information of the client.
-/// *`time_elapsed` - The time since the last ping.
+/// * `reader` - The stream to read from.
+/// * `max_frame_size` - The largest body length that will be accepted.
+/// * `parse_header` - Decodes and validates the header of the frame.
 ///
 /// # Returns
-/// * `Some(bool)` - True if the client is still connected, false if not.
-/// * `None` - There was no ping sent.
-///
-fn send_ping_message(net_info: &mut NetworkInfo, time_elapsed: Duration) -> Option<bool> {
-    if time_elapsed.as_secs() > MIN_TIME_BETWEEN_PINGS {
-        match send_message(net_info, &json!({"kind": "ping"})) {
-            Ok(_) => Some(true),
-            Err(_) => Some(false),
-        }
-    } else {
-        None
-    }
+/// * `Ok(body)` - The body of the frame.
+/// * `Err(e)` - The stream broke or the header was rejected, the connection has to be closed.
+///
+async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_frame_size: u32,
+    parse_header: HeaderParser,
+) -> Result<Vec<u8>, Error> {
+    let mut header = [0; FRAME_HEADER_SIZE];
+    reader.read_exact(&mut header).await?;
+    // the length is checked before the body is allocated
+    let header =
+        parse_header(&header, max_frame_size).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+    let mut body = vec![0; header.length as usize];
+    reader.read_exact(&mut body).await?;
+    Ok(body)
 }
 
-/// Initializes the client for the first time.
+/// Encrypts a message for sending.
 ///
 /// # Arguments
-/// * `net_info` - The network information of the client.
-/// * `lobby_tx` - The channel to send messages to the broadcast thread.
+/// * `msg` - The message to be sent.
+/// * `session` - The session of the connection.
 ///
-fn client_initialize(net_info: &mut NetworkInfo, lobby_tx: &mpsc::Sender<Value>) {
-    let _ = net_info
-        .tcp_stream
-        .set_read_timeout(Some(Duration::from_millis(20)));
+/// # Returns
+/// * `Ok(frame)` - The frame to be written to the connection.
+/// * `Err(e)` - The message could not be packed, the connection has to be closed.
+///
+fn encode_message(msg: &Message, session: &Mutex<Session>) -> Result<Vec<u8>, Error> {
+    encrypt_json(serde_json::to_vec(msg)?, &mut session.lock().unwrap())
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
 
-    let mut buffer = [0; 32];
-    let _ = net_info.tcp_stream.read(&mut buffer);
+/// Performs the handshake with a new client.
+///
+/// Reads the hello of the client, agrees on the protocol version and encryption mode,
+/// checks the username and finds the requested lobby,
+/// clients that cannot be served are told why before the connection is closed.
+/// Clients that only browse the lobbies get the list of public lobbies instead.
+/// Then performs the authenticated key exchange: the server sends its ephemeral public key,
+/// the client id and its long-term identity key, reads the ephemeral key of the client
+/// and finally signs the handshake transcript, so the client can detect a man-in-the-middle.
+///
+/// # Arguments
+/// * `tcp_stream` - The tcp_stream of the new client.
+/// * `id` - The id assigned to the client.
+/// * `settings` - The identity and limits of the server.
+/// * `lobbies` - The lobbies of the server.
+///
+/// # Returns
+/// * `Ok(Some((session, hello, lobby)))` - The session holding the shared key, the accepted hello of the client
+///   and the lobby it joins.
+/// * `Ok(None)` - The client only asked for the list of lobbies.
+/// * `Err(e)` - The handshake failed or the client was rejected.
+///
+async fn handshake(
+    tcp_stream: &mut TcpStream,
+    id: i64,
+    settings: &ConnectionSettings,
+    lobbies: &Mutex<LobbyRegistry>,
+) -> Result<Option<(Session, ClientHello, Arc<Mutex<LobbyState>>)>, Error> {
+    let hello_body = read_frame(
+        tcp_stream,
+        settings.max_frame_size,
+        FrameHeader::parse_any_version,
+    )
+    .await?;
+    let hello: ClientHello = serde_json::from_slice(&hello_body)?;
+    let mode = match negotiate(&hello, &settings.encryption_modes) {
+        Ok(mode) => mode,
+        Err(reason) => return Err(reject(tcp_stream, reason).await),
+    };
+    if let LobbyRequest::Browse = hello.lobby {
+        let list = ServerHello::LobbyList {
+            lobbies: LobbyRegistry::list_public_lobbies(lobbies),
+        };
+        tcp_stream
+            .write_all(&encode_handshake_message(&list)?)
+            .await?;
+        println!("Sent the lobby list to client {}", id);
+        return Ok(None);
+    }
+    let resolved = lobbies
+        .lock()
+        .unwrap()
+        .resolve(&hello.lobby, &hello.username);
+    let (lobby_info, lobby) = match resolved {
+        Ok(resolved) => resolved,
+        Err(reason) => return Err(reject(tcp_stream, reason).await),
+    };
 
-    let mut conn = BufReader::new(&net_info.tcp_stream);
-    let mut username = String::new();
-    let _ = conn.read_line(&mut username);
+    let identity = &settings.identity;
+    let (public_key, secret_key) = generate_keypair();
+    let accept = ServerHello::Accept {
+        protocol_version: PROTOCOL_VERSION,
+        encryption_mode: mode,
+        id,
+        public_key: public_key.to_bytes(),
+        identity_key: identity.public.to_bytes(),
+        lobby: lobby_info.clone(),
+    };
+    let accept_body = serde_json::to_vec(&accept)?;
+    tcp_stream
+        .write_all(&encode_handshake_frame(&accept_body)?)
+        .await?;
 
-    let username = username.trim().to_string();
+    let key_exchange = read_frame(tcp_stream, settings.max_frame_size, FrameHeader::parse).await?;
+    let key_exchange: ClientKeyExchange = serde_json::from_slice(&key_exchange)?;
 
-    let client_public: PublicKey = PublicKey::from(buffer);
-    let shared_secret = net_info
-        .secret_key
-        .as_ref()
-        .unwrap()
-        .diffie_hellman(&client_public);
-    net_info.key = *Key::from_slice(shared_secret.as_bytes());
+    let client_public = PublicKey::from(key_exchange.public_key);
+    let transcript = handshake_transcript(
+        &hello_body,
+        &accept_body,
+        &public_key,
+        id,
+        identity.public.as_bytes(),
+        &client_public,
+        mode,
+    );
+    let finished = HandshakeFinished::new(identity.sign(&transcript).to_bytes().to_vec());
+    tcp_stream
+        .write_all(&encode_handshake_message(&finished)?)
+        .await?;
 
-    let _ = lobby_tx.send(json!({"kind": "user_init", "id": net_info.id , "username": username}));
+    let shared_secret = secret_key.diffie_hellman(&client_public);
+    let session = Session::new(shared_secret.as_bytes(), &transcript, Side::Server, mode);
+    println!(
+        "Client {} connected in {} mode using version {} to lobby {}",
+        id, mode, hello.client_version, lobby_info.id
+    );
+    Ok(Some((session, hello, lobby)))
 }
 
-/// The main loop to handle each individual client.
+/// Tells a client why it cannot be served.
 ///
-/// This function should be run in a separate thread.
-/// This function reads in the username and create the
-/// shared secret for the client and server to communicate
+/// # Arguments
+/// * `tcp_stream` - The tcp_stream of the client.
+/// * `reason` - Why the client is rejected.
+///
+/// # Returns
+/// * `e` - The error ending the handshake.
+///
+async fn reject(tcp_stream: &mut TcpStream, reason: RejectReason) -> Error {
+    let reject = ServerHello::Reject {
+        reason: reason.clone(),
+    };
+    let sent = match encode_handshake_message(&reject) {
+        Ok(frame) => tcp_stream.write_all(&frame).await,
+        Err(e) => Err(e),
+    };
+    match sent {
+        Ok(()) => Error::new(
+            ErrorKind::ConnectionRefused,
+            format!("Rejected client: {}", reason),
+        ),
+        Err(e) => e,
+    }
+}
+
+/// Reads the messages of a client and forwards them to its lobby until the connection breaks.
 ///
 /// # Arguments
-/// * `net_info` - The network information of the client.
-/// * `lobby_tx` - The channel to send messages to the broadcast thread of a lobby.
-/// * `client_tx` - The channel to receive messages from the client.
-///
-pub(crate) fn handle_client(
-    mut net_info: NetworkInfo,
-    lobby_tx: mpsc::Sender<Value>,
-    client_rx: mpsc::Receiver<Value>,
+/// * `reader` - The reading half of the connection.
+/// * `player_id` - The id assigned to the client.
+/// * `max_frame_size` - The maximum size of a frame received from the client.
+/// * `session` - The session of the connection.
+/// * `lobby_tx` - The channel to send messages to the lobby.
+///
+async fn read_client_messages(
+    mut reader: OwnedReadHalf,
+    player_id: i64,
+    max_frame_size: u32,
+    session: Arc<Mutex<Session>>,
+    lobby_tx: mpsc::Sender<Message>,
 ) {
-    client_initialize(&mut net_info, &lobby_tx);
-    let mut keepalive = Instant::now();
-    let player_id = net_info.id;
-
-    //Start of the client thread's main loop to read messages and send keep-alive pings
     loop {
-        if let Ok(msg) = read_tcp_message(&mut net_info) {
-            let _ = lobby_tx.send(msg);
-            keepalive = Instant::now();
-        }
+        let frame = match read_frame(&mut reader, max_frame_size, FrameHeader::parse).await {
+            Ok(frame) => frame,
+            Err(e) => {
+                if e.kind() != ErrorKind::UnexpectedEof {
+                    println!("Closing connection to client {}: {}", player_id, e);
+                }
+                return;
+            }
+        };
+        let msg = match decrypt_message(&frame, &mut session.lock().unwrap()) {
+            Ok(msg) => msg,
+            Err(e) if e.is::<serde_json::Error>() => {
+                println!(
+                    "Dropping malformed message from client {}: {}",
+                    player_id, e
+                );
+                continue;
+            }
+            Err(e) => match e.downcast_ref::<ProtocolError>() {
+                Some(protocol_error) if protocol_error.is_fatal() => {
+                    println!("Closing connection to client {}: {}", player_id, e);
+                    return;
+                }
+                _ => {
+                    println!("Dropping message from client {}: {}", player_id, e);
+                    continue;
+                }
+            },
+        };
 
-        // Check if rx has messages waiting and if yes, send them to the client
-        if let Ok(msg) = client_rx.try_recv() {
-            let _ = send_message(&mut net_info, &msg);
+        // the id inside a message is only trusted if it is the id of this connection
+        match msg.client_sender() {
+            Some(sender_id) if sender_id == player_id => {
+                if lobby_tx.send(msg).await.is_err() {
+                    return;
+                }
+            }
+            Some(sender_id) => println!(
+                "Rejected message from client {} claiming to be player {}",
+                player_id, sender_id
+            ),
+            None => println!("Rejected server-only message from client {}", player_id),
         }
+    }
+}
 
-        match send_ping_message(&mut net_info, Instant::now().duration_since(keepalive)) {
-            Some(false) => {
-                let _ = lobby_tx.send(json!(DisconnectMessage::new(player_id)));
+/// Sends the messages queued for a client and keeps the connection alive with pings.
+///
+/// # Arguments
+/// * `writer` - The writing half of the connection.
+/// * `player_id` - The id assigned to the client.
+/// * `session` - The session of the connection.
+/// * `client_rx` - The queue of messages to be sent to the client.
+///
+async fn write_client_messages(
+    mut writer: OwnedWriteHalf,
+    player_id: i64,
+    session: Arc<Mutex<Session>>,
+    mut client_rx: mpsc::Receiver<Message>,
+) {
+    let ping_interval = Duration::from_secs(MIN_TIME_BETWEEN_PINGS);
+    loop {
+        let msg = match time::timeout(ping_interval, client_rx.recv()).await {
+            Ok(Some(msg)) => msg,
+            // the lobby has dropped the client
+            Ok(None) => return,
+            // nothing was sent for a while, check if the client is still connected
+            Err(_) => Message::Ping,
+        };
+        let frame = match encode_message(&msg, &session) {
+            Ok(frame) => frame,
+            Err(e) => {
+                println!("Closing connection to client {}: {}", player_id, e);
                 return;
             }
-            Some(true) => keepalive = Instant::now(),
-            None => {}
+        };
+        if writer.write_all(&frame).await.is_err() {
+            return;
         }
     }
 }
+
+/// Handles the connection of a single client.
+///
+/// This function should be run in a separate task.
+/// After the handshake the client joins the lobby it asked for, then one task reads the messages of the client
+/// and another sends the messages queued for it. When either of them stops the connection is closed
+/// and the player is removed from the lobby.
+///
+/// # Arguments
+/// * `tcp_stream` - The tcp_stream of the client.
+/// * `id` - The id assigned to the client.
+/// * `settings` - The identity and limits of the server.
+/// * `lobbies` - The lobbies of the server.
+///
+pub(crate) async fn handle_client(
+    mut tcp_stream: TcpStream,
+    id: i64,
+    settings: Arc<ConnectionSettings>,
+    lobbies: Arc<Mutex<LobbyRegistry>>,
+) {
+    let handshake = time::timeout(
+        Duration::from_secs(HANDSHAKE_TIMEOUT),
+        handshake(&mut tcp_stream, id, &settings, &lobbies),
+    )
+    .await
+    .unwrap_or_else(|_| Err(Error::new(ErrorKind::TimedOut, "Handshake timed out")));
+    let (session, hello, lobby) = match handshake {
+        Ok(Some(handshake)) => handshake,
+        Ok(None) => return,
+        Err(e) => {
+            println!("Handshake with client {} failed: {}", id, e);
+            return;
+        }
+    };
+
+    let (client_tx, client_rx) = mpsc::channel(OUTBOUND_QUEUE_SIZE);
+    let lobby_tx = {
+        let mut lobby = lobby.lock().unwrap();
+        lobby.add_client_tx(id, client_tx);
+        lobby.lobby_tx()
+    };
+    let username = hello.username.trim().to_string();
+    if lobby_tx
+        .send(Message::UserInit(UserInit::new(id, username)))
+        .await
+        .is_err()
+    {
+        println!("Lobby of client {} was closed before it joined", id);
+        return;
+    }
+    let session = Arc::new(Mutex::new(session));
+
+    let (reader, writer) = tcp_stream.into_split();
+    let mut reader_task = tokio::spawn(read_client_messages(
+        reader,
+        id,
+        settings.max_frame_size,
+        session.clone(),
+        lobby_tx.clone(),
+    ));
+    let mut writer_task = tokio::spawn(write_client_messages(writer, id, session, client_rx));
+    // the connection is closed as soon as one direction fails
+    tokio::select! {
+        _ = &mut reader_task => writer_task.abort(),
+        _ = &mut writer_task => reader_task.abort(),
+    }
+    let _ = lobby_tx
+        .send(Message::Disconnect(DisconnectMessage::new(id)))
+        .await;
+}
@@ -1,4 +1,12 @@
 use rust_scribble_common::gamestate_common::Player;
+use rust_scribble_common::settings_common::{DrawerScoring, GuesserScoring};
+
+/// Points a player gets for the best possible guess or drawing.
+const FULL_REWARD: i64 = 100;
+/// Share of the reward the next guesser or drawer reward differs by in the exponential strategies.
+const EXPONENTIAL_STEP: f64 = 0.2;
+/// Share of the reward a guesser loses for every letter revealed before the guess.
+const HINT_PENALTY: f64 = 0.1;
 
 pub trait RewardStrategyGuesser: Send + Sync {
     fn reward_points_to_guesser(
@@ -7,6 +15,7 @@ pub trait RewardStrategyGuesser: Send + Sync {
         number_of_guessers: usize,
         players_already_guessed: usize,
         time_left: i64,
+        hints_revealed: usize,
     );
 }
 
@@ -17,9 +26,62 @@ pub trait RewardStrategyDrawer: Send + Sync {
         number_of_guessers: usize,
         players_already_guessed: usize,
         time_left: i64,
+        hints_revealed: usize,
     );
 }
 
+/// Creates the strategy rewarding the guessers of a lobby.
+///
+/// # Arguments
+/// * `scoring` - The scoring chosen in the settings of the lobby.
+/// * `round_time` - The seconds a turn lasts.
+pub fn guesser_strategy(
+    scoring: GuesserScoring,
+    round_time: i64,
+) -> Box<dyn RewardStrategyGuesser> {
+    match scoring {
+        GuesserScoring::Equal => Box::new(EqualRewardStrategy {
+            full_reward: FULL_REWARD,
+        }),
+        GuesserScoring::TimeBased => Box::new(TimeBasedRewardStrategy {
+            full_reward: FULL_REWARD,
+            initial_time: round_time,
+            hint_penalty: HINT_PENALTY,
+        }),
+        GuesserScoring::LinearlyDecreasing => Box::new(LinearlyDecreasingRewardStrategy {
+            full_reward: FULL_REWARD,
+        }),
+        GuesserScoring::ExponentiallyDecreasing => {
+            Box::new(ExponentiallyDecreasingRewardStrategy {
+                full_reward: FULL_REWARD,
+                decrease_per_position: EXPONENTIAL_STEP,
+            })
+        }
+    }
+}
+
+/// Creates the strategy rewarding the drawer of a lobby.
+///
+/// # Arguments
+/// * `scoring` - The scoring chosen in the settings of the lobby.
+/// * `round_time` - The seconds a turn lasts.
+pub fn drawer_strategy(scoring: DrawerScoring, round_time: i64) -> Box<dyn RewardStrategyDrawer> {
+    match scoring {
+        DrawerScoring::Equal => Box::new(EqualRewardStrategy {
+            full_reward: FULL_REWARD,
+        }),
+        DrawerScoring::TimeBased => Box::new(TimeBasedRewardStrategy {
+            full_reward: FULL_REWARD,
+            initial_time: round_time,
+            hint_penalty: 0.0,
+        }),
+        DrawerScoring::ExponentiallyIncreasing => Box::new(ExponentiallyIncreasingRewardStrategy {
+            last_reward: FULL_REWARD,
+            increase_per_position: EXPONENTIAL_STEP,
+        }),
+    }
+}
+
 pub struct EqualRewardStrategy {
     pub full_reward: i64,
 }
@@ -27,6 +89,8 @@ pub struct EqualRewardStrategy {
 pub struct TimeBasedRewardStrategy {
     pub full_reward: i64,
     pub initial_time: i64,
+    /// Share of the reward lost for every revealed letter, guesses are worth less the more hints they had.
+    pub hint_penalty: f64,
 }
 
 pub struct LinearlyDecreasingRewardStrategy {
@@ -51,6 +115,7 @@ impl RewardStrategyGuesser for EqualRewardStrategy {
         _number_of_guessers: usize,
         _players_already_guessed: usize,
         _time_left: i64,
+        _hints_revealed: usize,
     ) {
         player.score += self.full_reward;
     }
@@ -63,6 +128,7 @@ impl RewardStrategyDrawer for EqualRewardStrategy {
         number_of_guessers: usize,
         _players_already_guessed: usize,
         _time_left: i64,
+        _hints_revealed: usize,
     ) {
         let points_per_correct_guess = self.full_reward / number_of_guessers as i64;
         player.score += points_per_correct_guess
@@ -76,9 +142,12 @@ impl RewardStrategyGuesser for TimeBasedRewardStrategy {
         _number_of_guessers: usize,
         _players_already_guessed: usize,
         time_left: i64,
+        hints_revealed: usize,
     ) {
-        player.score +=
-            ((time_left as f64 / self.initial_time as f64) * self.full_reward as f64) as i64;
+        let hint_factor = (1.0 - self.hint_penalty * hints_revealed as f64).max(0.0);
+        player.score += ((time_left as f64 / self.initial_time as f64)
+            * hint_factor
+            * self.full_reward as f64) as i64;
     }
 }
 
@@ -89,6 +158,7 @@ impl RewardStrategyDrawer for TimeBasedRewardStrategy {
         number_of_guessers: usize,
         _players_already_guessed: usize,
         time_left: i64,
+        _hints_revealed: usize,
     ) {
         player.score += ((time_left as f64 / self.initial_time as f64)
             * (self.full_reward as f64 / number_of_guessers as f64)) as i64;
@@ -102,10 +172,11 @@ impl RewardStrategyGuesser for LinearlyDecreasingRewardStrategy {
         number_of_guessers: usize,
         players_already_guessed: usize,
         _time_left: i64,
+        _hints_revealed: usize,
     ) {
+        // players_already_guessed does not include this guess, the first guesser gets the full reward
         let points_for_last_guesser = self.full_reward / number_of_guessers as i64;
-        player.score +=
-            self.full_reward - points_for_last_guesser * (players_already_guessed - 1) as i64;
+        player.score += self.full_reward - points_for_last_guesser * players_already_guessed as i64;
     }
 }
 
@@ -116,6 +187,7 @@ impl RewardStrategyGuesser for ExponentiallyDecreasingRewardStrategy {
         _number_of_guessers: usize,
         players_already_guessed: usize,
         _time_left: i64,
+        _hints_revealed: usize,
     ) {
         player.score += (self.full_reward as f64
             * ((1.0 - self.decrease_per_position).powi(players_already_guessed as i32)) as f64)
@@ -130,6 +202,7 @@ impl RewardStrategyDrawer for ExponentiallyIncreasingRewardStrategy {
         number_of_guessers: usize,
         players_already_guessed: usize,
         _time_left: i64,
+        _hints_revealed: usize,
     ) {
         player.score += (self.last_reward as f64
             * ((1.0 - self.increase_per_position)
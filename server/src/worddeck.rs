@@ -0,0 +1,126 @@
+use std::collections::{HashMap, VecDeque};
+
+use rand::Rng;
+
+use crate::wordpack::WordEntry;
+
+/// Number of chosen words remembered to keep them from coming up again soon after a reshuffle.
+const RECENT_WORDS: usize = 100;
+
+/// The words of a lobby, drawn like cards from a shuffled deck.
+///
+/// Every word is offered once before any word is offered again.
+/// When the deck runs out it is reshuffled, the words chosen most recently go to the bottom,
+/// so they do not repeat right away, not even in the next match.
+pub(crate) struct WordDeck {
+    words: Vec<String>,
+    /// The other accepted spellings of the words.
+    aliases: HashMap<String, Vec<String>>,
+    /// The words left to draw, the next word is at the end.
+    pile: Vec<String>,
+    /// The words chosen most recently, the newest at the back.
+    recent: VecDeque<String>,
+}
+
+impl WordDeck {
+    /// Creates a shuffled deck.
+    /// A word that is in several packs is only added once, with the aliases of all packs.
+    ///
+    /// # Arguments
+    /// * `entries` - The words of the deck.
+    pub fn new(entries: Vec<WordEntry>) -> Self {
+        let mut words = Vec::new();
+        let mut aliases: HashMap<String, Vec<String>> = HashMap::new();
+        for entry in entries {
+            match aliases.get_mut(&entry.word) {
+                Some(known) => known.extend(entry.aliases),
+                None => {
+                    words.push(entry.word.clone());
+                    aliases.insert(entry.word, entry.aliases);
+                }
+            }
+        }
+        let mut deck = WordDeck {
+            words,
+            aliases,
+            pile: Vec::new(),
+            recent: VecDeque::new(),
+        };
+        deck.reshuffle(&[]);
+        deck
+    }
+
+    /// Draws words from the top of the deck, the deck is reshuffled when it runs out.
+    ///
+    /// # Arguments
+    /// * `count` - The number of words to draw.
+    ///
+    /// # Returns
+    /// * `words` - Different words, fewer than asked for if the deck does not have enough words.
+    pub fn draw(&mut self, count: usize) -> Vec<String> {
+        let mut drawn = Vec::new();
+        while drawn.len() < count.min(self.words.len()) {
+            if self.pile.is_empty() {
+                self.reshuffle(&drawn);
+            }
+            match self.pile.pop() {
+                Some(word) => drawn.push(word),
+                None => break,
+            }
+        }
+        drawn
+    }
+
+    /// The other spellings accepted as a correct guess of a word.
+    ///
+    /// # Arguments
+    /// * `word` - A word of the deck.
+    pub fn aliases(&self, word: &str) -> &[String] {
+        self.aliases
+            .get(word)
+            .map_or(&[], |aliases| aliases.as_slice())
+    }
+
+    /// Puts words that were drawn but not used back at the bottom of the deck.
+    ///
+    /// # Arguments
+    /// * `words` - The words to put back.
+    pub fn put_back(&mut self, words: Vec<String>) {
+        self.pile.splice(0..0, words);
+    }
+
+    /// Remembers a word as used, so it is not drawn again soon after the next reshuffle.
+    ///
+    /// # Arguments
+    /// * `word` - The word that was used.
+    pub fn mark_used(&mut self, word: &str) {
+        self.recent.retain(|recent| recent != word);
+        self.recent.push_back(word.to_string());
+        if self.recent.len() > RECENT_WORDS {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Fills the pile with all words except the ones currently held by the players.
+    /// The recently used words end up at the bottom, the newest one last.
+    ///
+    /// # Arguments
+    /// * `held` - The words that are drawn and not put back yet.
+    fn reshuffle(&mut self, held: &[String]) {
+        let mut fresh: Vec<String> = self
+            .words
+            .iter()
+            .filter(|word| !held.contains(word) && !self.recent.contains(word))
+            .cloned()
+            .collect();
+        rand::thread_rng().shuffle(&mut fresh);
+        self.pile = self
+            .recent
+            .iter()
+            .rev()
+            .filter(|word| !held.contains(word) && self.words.contains(word))
+            .cloned()
+            .chain(fresh)
+            .collect();
+    }
+}
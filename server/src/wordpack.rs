@@ -0,0 +1,201 @@
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Error;
+use std::path::Path;
+
+use rust_scribble_common::settings_common::{Difficulty, WordPackInfo};
+use serde::Deserialize;
+
+/// A word of a word pack.
+#[derive(Deserialize, Clone, Debug)]
+pub(crate) struct WordEntry {
+    pub word: String,
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub difficulty: Difficulty,
+    /// Other spellings that are accepted as a correct guess.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+impl WordEntry {
+    /// A word of medium difficulty without category and aliases.
+    ///
+    /// # Arguments
+    /// * `word` - The word.
+    pub fn new(word: String) -> Self {
+        WordEntry {
+            word,
+            category: None,
+            difficulty: Difficulty::default(),
+            aliases: Vec::new(),
+        }
+    }
+}
+
+/// A set of words loaded from a JSON file of the word pack directory.
+///
+/// ```json
+/// {
+///     "name": "Classic",
+///     "language": "English",
+///     "words": [
+///         { "word": "koala bear", "category": "animals", "difficulty": "easy", "aliases": ["koala"] }
+///     ]
+/// }
+/// ```
+/// The name defaults to the file name, category, difficulty and aliases are optional.
+#[derive(Deserialize, Debug)]
+pub(crate) struct WordPack {
+    #[serde(default)]
+    pub name: String,
+    pub language: String,
+    pub words: Vec<WordEntry>,
+}
+
+impl WordPack {
+    /// Describes the pack for the host choosing the word packs of a lobby.
+    pub fn info(&self) -> WordPackInfo {
+        let categories: BTreeSet<String> = self
+            .words
+            .iter()
+            .filter_map(|entry| entry.category.clone())
+            .collect();
+        WordPackInfo {
+            name: self.name.clone(),
+            language: self.language.clone(),
+            categories: categories.into_iter().collect(),
+            words: self.words.len(),
+        }
+    }
+
+    /// Cleans up the words of the pack and describes what was wrong with them.
+    /// Blank words are skipped, surrounding whitespace is removed and repeated words are only kept once.
+    ///
+    /// # Returns
+    /// * `problems` - A description of every problem found.
+    fn validate(&mut self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut first_entries: HashMap<String, usize> = HashMap::new();
+        let mut words = Vec::new();
+        for (index, mut entry) in self.words.drain(..).enumerate() {
+            let entry_number = index + 1;
+            let word = entry.word.trim().to_string();
+            if word.is_empty() {
+                problems.push(format!("word {} is blank", entry_number));
+                continue;
+            }
+            if word != entry.word {
+                problems.push(format!(
+                    "word {} has whitespace around \"{}\"",
+                    entry_number, word
+                ));
+            }
+            let key = word.to_lowercase();
+            if let Some(first_entry) = first_entries.get(&key) {
+                problems.push(format!(
+                    "word {} repeats \"{}\" from word {}",
+                    entry_number, word, first_entry
+                ));
+                continue;
+            }
+            first_entries.insert(key, entry_number);
+            entry.word = word;
+            entry.aliases = entry
+                .aliases
+                .iter()
+                .map(|alias| alias.trim().to_string())
+                .filter(|alias| !alias.is_empty())
+                .collect();
+            words.push(entry);
+        }
+        if words.is_empty() {
+            problems.push("there are no words".to_string());
+        }
+        self.words = words;
+        problems
+    }
+}
+
+/// Loads every word pack in a directory.
+/// Packs that cannot be read are skipped and the problems found in a pack are reported.
+///
+/// # Arguments
+/// * `dir` - The directory holding the word packs as JSON files.
+///
+/// # Returns
+/// * `Ok(packs)` - The packs that have words, ordered by name.
+/// * `Err(e)` - The directory cannot be read.
+pub(crate) fn load_word_packs(dir: impl AsRef<Path>) -> Result<Vec<WordPack>, Error> {
+    let mut paths: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension() == Some(OsStr::new("json")))
+        .collect();
+    paths.sort();
+    let mut packs: Vec<WordPack> = Vec::new();
+    for path in paths {
+        let pack = fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| serde_json::from_slice::<WordPack>(&json).map_err(|e| e.to_string()));
+        let mut pack = match pack {
+            Ok(pack) => pack,
+            Err(e) => {
+                println!("Skipping word pack {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        if pack.name.trim().is_empty() {
+            pack.name = path
+                .file_stem()
+                .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+        }
+        for problem in pack.validate() {
+            println!("Word pack {}: {}", path.display(), problem);
+        }
+        if pack.words.is_empty() {
+            continue;
+        }
+        if packs.iter().any(|other| other.name == pack.name) {
+            println!(
+                "Skipping word pack {}: another pack is called \"{}\"",
+                path.display(),
+                pack.name
+            );
+            continue;
+        }
+        println!(
+            "Loaded word pack \"{}\" ({}) with {} words",
+            pack.name,
+            pack.language,
+            pack.words.len()
+        );
+        packs.push(pack);
+    }
+    packs.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(packs)
+}
+
+/// Collects the words a lobby plays with.
+///
+/// # Arguments
+/// * `packs` - The word packs of the server.
+/// * `names` - The names of the packs chosen for the lobby.
+/// * `difficulties` - The difficulties chosen for the lobby.
+///
+/// # Returns
+/// * `words` - The words of the chosen packs with one of the chosen difficulties.
+pub(crate) fn select_words(
+    packs: &[WordPack],
+    names: &[String],
+    difficulties: &[Difficulty],
+) -> Vec<WordEntry> {
+    packs
+        .iter()
+        .filter(|pack| names.contains(&pack.name))
+        .flat_map(|pack| pack.words.iter())
+        .filter(|entry| difficulties.contains(&entry.difficulty))
+        .cloned()
+        .collect()
+}